@@ -1,16 +1,17 @@
-use failure::format_err;
-use failure::Error;
-use lazy_static::lazy_static;
 use log::debug;
-use regex::{Captures, Regex};
 use serde::de;
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 
-use crate::ops::Result;
+use crate::error::{Error, Result};
+use crate::expression::evaluate;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// Unit multipliers, declared in ascending order so the derived
+/// ordering matches the numerical one.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Magnitude {
+    #[default]
     Unit,
     K,
     Ki,
@@ -18,60 +19,12 @@ pub enum Magnitude {
     Mi,
     G,
     Gi,
-}
-
-impl std::cmp::PartialOrd for Magnitude {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl std::cmp::Ord for Magnitude {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match (self, other) {
-            (Magnitude::Unit, Magnitude::Unit) => std::cmp::Ordering::Equal,
-            (Magnitude::Unit, _) => std::cmp::Ordering::Less,
-
-            (Magnitude::K, Magnitude::Unit) => std::cmp::Ordering::Greater,
-            (Magnitude::K, Magnitude::K) => std::cmp::Ordering::Equal,
-            (Magnitude::K, _) => std::cmp::Ordering::Less,
-
-            (Magnitude::Ki, Magnitude::Unit) => std::cmp::Ordering::Greater,
-            (Magnitude::Ki, Magnitude::K) => std::cmp::Ordering::Greater,
-            (Magnitude::Ki, Magnitude::Ki) => std::cmp::Ordering::Equal,
-            (Magnitude::Ki, _) => std::cmp::Ordering::Less,
-
-            (Magnitude::M, Magnitude::Unit) => std::cmp::Ordering::Greater,
-            (Magnitude::M, Magnitude::K) => std::cmp::Ordering::Greater,
-            (Magnitude::M, Magnitude::Ki) => std::cmp::Ordering::Greater,
-            (Magnitude::M, Magnitude::M) => std::cmp::Ordering::Equal,
-            (Magnitude::M, _) => std::cmp::Ordering::Less,
-
-            (Magnitude::Mi, Magnitude::Unit) => std::cmp::Ordering::Greater,
-            (Magnitude::Mi, Magnitude::K) => std::cmp::Ordering::Greater,
-            (Magnitude::Mi, Magnitude::Ki) => std::cmp::Ordering::Greater,
-            (Magnitude::Mi, Magnitude::M) => std::cmp::Ordering::Greater,
-            (Magnitude::Mi, Magnitude::Mi) => std::cmp::Ordering::Equal,
-            (Magnitude::Mi, _) => std::cmp::Ordering::Less,
-
-            (Magnitude::G, Magnitude::Unit) => std::cmp::Ordering::Greater,
-            (Magnitude::G, Magnitude::K) => std::cmp::Ordering::Greater,
-            (Magnitude::G, Magnitude::Ki) => std::cmp::Ordering::Greater,
-            (Magnitude::G, Magnitude::M) => std::cmp::Ordering::Greater,
-            (Magnitude::G, Magnitude::Mi) => std::cmp::Ordering::Greater,
-            (Magnitude::G, Magnitude::G) => std::cmp::Ordering::Equal,
-            (Magnitude::G, _) => std::cmp::Ordering::Less,
-
-            (Magnitude::Gi, Magnitude::Gi) => std::cmp::Ordering::Equal,
-            (Magnitude::Gi, _) => std::cmp::Ordering::Greater,
-        }
-    }
-}
-
-impl Default for Magnitude {
-    fn default() -> Self {
-        Magnitude::Unit
-    }
+    T,
+    Ti,
+    P,
+    Pi,
+    E,
+    Ei,
 }
 
 impl Magnitude {
@@ -84,9 +37,15 @@ impl Magnitude {
             "Mi" => Ok(Magnitude::Mi),
             "G" => Ok(Magnitude::G),
             "Gi" => Ok(Magnitude::Gi),
+            "T" => Ok(Magnitude::T),
+            "Ti" => Ok(Magnitude::Ti),
+            "P" => Ok(Magnitude::P),
+            "Pi" => Ok(Magnitude::Pi),
+            "E" => Ok(Magnitude::E),
+            "Ei" => Ok(Magnitude::Ei),
             _ => {
                 debug!("No idea what to do with {} as magnitude ", mag_str);
-                Err(format_err!("Unknown Magnitude {}", mag_str))
+                Err(err!(Parse, "Unknown Magnitude {}", mag_str))
             }
         }
     }
@@ -100,12 +59,14 @@ impl Magnitude {
             Magnitude::Mi => 1024u64 * 1024u64,
             Magnitude::G => 1000u64 * 1000u64 * 1000u64,
             Magnitude::Gi => 1024u64 * 1024u64 * 1024u64,
+            Magnitude::T => 1000u64 * 1000u64 * 1000u64 * 1000u64,
+            Magnitude::Ti => 1024u64 * 1024u64 * 1024u64 * 1024u64,
+            Magnitude::P => 1000u64 * 1000u64 * 1000u64 * 1000u64 * 1000u64,
+            Magnitude::Pi => 1024u64 * 1024u64 * 1024u64 * 1024u64 * 1024u64,
+            Magnitude::E => 1000u64 * 1000u64 * 1000u64 * 1000u64 * 1000u64 * 1000u64,
+            Magnitude::Ei => 1024u64 * 1024u64 * 1024u64 * 1024u64 * 1024u64 * 1024u64,
         }
     }
-
-    pub fn as_usize(&self) -> usize {
-        self.as_u64() as usize
-    }
 }
 
 impl fmt::Display for Magnitude {
@@ -118,6 +79,12 @@ impl fmt::Display for Magnitude {
             Magnitude::Mi => "MiB",
             Magnitude::G => "GB",
             Magnitude::Gi => "GiB",
+            Magnitude::T => "TB",
+            Magnitude::Ti => "TiB",
+            Magnitude::P => "PB",
+            Magnitude::Pi => "PiB",
+            Magnitude::E => "EB",
+            Magnitude::Ei => "EiB",
         };
         write!(f, "{}", printable_mag)
     }
@@ -133,11 +100,52 @@ impl ByteOffset {
     pub fn new(num: u64, magnitude: Magnitude) -> Self {
         Self { num, magnitude }
     }
-    pub fn as_u64(&self) -> u64 {
-        self.magnitude.as_u64() * self.num
+    pub fn from_u64(num: u64) -> Self {
+        Self::new(num, Magnitude::Unit)
+    }
+    pub fn as_u64(&self) -> Result<u64> {
+        self.num
+            .checked_mul(self.magnitude.as_u64())
+            .ok_or_else(|| err!(OutOfBounds, "Byte offset {} exceeds the 64 bit range", self))
+    }
+    pub fn as_usize(&self) -> Result<usize> {
+        let num = self.as_u64()?;
+        usize::try_from(num).map_err(|_| {
+            err!(
+                OutOfBounds,
+                "Byte offset {} exceeds the platform address space",
+                self
+            )
+        })
+    }
+
+    /// Sum of both offsets, fails instead of wrapping around.
+    pub fn checked_add(&self, rhs: &Self) -> Result<Self> {
+        let num = self.as_u64()?.checked_add(rhs.as_u64()?).ok_or_else(|| {
+            err!(
+                OutOfBounds,
+                "Sum of {} and {} exceeds the 64 bit range",
+                self,
+                rhs
+            )
+        })?;
+        // output has always magnitude Unit
+        Ok(Self::from_u64(num))
+    }
+
+    /// Difference of both offsets, fails if `rhs` is greater than `self`.
+    pub fn checked_sub(&self, rhs: &Self) -> Result<Self> {
+        let num = self
+            .as_u64()?
+            .checked_sub(rhs.as_u64()?)
+            .ok_or_else(|| err!(OutOfBounds, "Subtracting {} from {} underflows", rhs, self))?;
+        // output has always magnitude Unit
+        Ok(Self::from_u64(num))
     }
-    pub fn as_usize(&self) -> usize {
-        self.magnitude.as_usize() * (self.num as usize)
+
+    /// Exact value, wide enough to never overflow, used for comparisons.
+    fn as_u128(&self) -> u128 {
+        u128::from(self.num) * u128::from(self.magnitude.as_u64())
     }
 }
 
@@ -159,7 +167,22 @@ impl<'de> de::Deserialize<'de> for ByteOffset {
             where
                 E: de::Error,
             {
-                ByteOffset::from_str(value).map_err(|e| E::custom(format!("{:?}", e)))
+                ByteOffset::from_str(value).map_err(|e| E::custom(e.to_string()))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> ::std::result::Result<ByteOffset, E>
+            where
+                E: de::Error,
+            {
+                Ok(ByteOffset::from_u64(value))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> ::std::result::Result<ByteOffset, E>
+            where
+                E: de::Error,
+            {
+                let value = u64::try_from(value).map_err(|e| E::custom(format!("{:?}", e)))?;
+                Ok(ByteOffset::from_u64(value))
             }
         }
         deserializer.deserialize_str(ByteOffsetVisitor)
@@ -169,78 +192,10 @@ impl<'de> de::Deserialize<'de> for ByteOffset {
 impl FromStr for ByteOffset {
     type Err = Error;
 
+    /// Parses a literal such as `0x100` or `4Ki`, or an arithmetic
+    /// expression of those, see [`crate::expression`].
     fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
-        lazy_static! {
-            static ref REGEX: Regex =
-                Regex::new(r"^(?:(0[xX]){1}([A-Fa-f0-9]+))|(?:([0-9]+)([KMGTE]i?)?)$").unwrap();
-        }
-
-        let byte_offset = REGEX
-            .captures(s)
-            .ok_or_else(|| {
-                Err::<Captures, Error>(format_err!("Failed to parse {} to ByteOffset", s))
-            })
-            .and_then(|captures| {
-                if captures.len() == 5 {
-                    let byte_offset: ByteOffset = match &captures.get(1) {
-                        Some(_) => {
-                            let num_str = &captures[2];
-                            let num = u64::from_str_radix(num_str, 16).map_err(|e| {
-                                Err::<Captures, Error>(format_err!(
-                                    "Failed to parse u64 from hex {}",
-                                    e
-                                ))
-                            })?;
-                            ByteOffset::new(num, Magnitude::Unit)
-                        }
-                        None => {
-                            let num_str = &captures[3];
-                            let magnitude_str = &captures.get(4).map_or("", |m| m.as_str());
-                            let num = num_str.parse::<u64>().map_err(|e| {
-                                Err::<Captures, Error>(format_err!("Failed to parse u64 {}", e))
-                            })?;
-                            let magnitude = Magnitude::parse(magnitude_str).map_err(|e| {
-                                Err::<Captures, Error>(format_err!(
-                                    "Failed to parse magnitude {}",
-                                    e
-                                ))
-                            })?;
-                            ByteOffset::new(num, magnitude)
-                        }
-                    };
-                    Ok(byte_offset)
-                } else {
-                    Ok(Default::default())
-                }
-            })
-            .map_err(|e| format_err!("{:?}", e))?;
-        Ok(byte_offset)
-    }
-}
-
-impl std::ops::Sub for ByteOffset {
-    type Output = Self;
-    fn sub(self, rhs: Self) -> Self {
-        let num = self.as_u64() - rhs.as_u64();
-        // output has always magnitude Unit, we'd need a from_u64()
-        // to parse from u64 into suitable magnitude?
-        ByteOffset {
-            num,
-            magnitude: Magnitude::Unit,
-        }
-    }
-}
-
-impl std::ops::Add for ByteOffset {
-    type Output = Self;
-    fn add(self, rhs: Self) -> Self {
-        let num = self.as_u64() + rhs.as_u64();
-        // output has always magnitude Unit, we'd need a from_u64()
-        // to parse from u64 into suitable magnitude?
-        ByteOffset {
-            num,
-            magnitude: Magnitude::Unit,
-        }
+        evaluate(s).map_err(|e| err!(Parse, "Failed to parse {} to ByteOffset: {}", s, e))
     }
 }
 
@@ -258,7 +213,7 @@ impl std::cmp::PartialOrd for ByteOffset {
 
 impl std::cmp::Ord for ByteOffset {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.as_u64().cmp(&other.as_u64())
+        self.as_u128().cmp(&other.as_u128())
     }
 }
 
@@ -268,7 +223,7 @@ mod test {
 
     #[test]
     fn bo_decimal_from_string() {
-        let byte_offset_string = vec!["0", "45K", "12Ki", "92M", "999Mi", "012G", "209Gi"];
+        let byte_offset_string = ["0", "45K", "12Ki", "92M", "999Mi", "012G", "209Gi"];
 
         let byte_offsets: Vec<ByteOffset> = byte_offset_string
             .iter()
@@ -286,7 +241,7 @@ mod test {
 
     #[test]
     fn bo_hex_from_string() {
-        let byte_offset_strings = vec!["0x0", "0x100", "0XFAcBd"];
+        let byte_offset_strings = ["0x0", "0x100", "0XFAcBd"];
         let byte_offsets: Vec<ByteOffset> = byte_offset_strings
             .iter()
             .map(|bo| ByteOffset::from_str(bo).expect("failed to parse"))
@@ -297,9 +252,47 @@ mod test {
         assert_eq!(byte_offsets[2], ByteOffset::new(1_027_261, Magnitude::Unit));
     }
 
+    #[test]
+    fn bo_large_magnitudes() {
+        let byte_offset_string = ["3T", "3Ti", "2P", "2Pi", "15E", "15Ei"];
+
+        let byte_offsets: Vec<u64> = byte_offset_string
+            .iter()
+            .map(|bo| ByteOffset::from_str(bo).expect("Failed to parse"))
+            .map(|bo| bo.as_u64().expect("Failed to convert"))
+            .collect();
+
+        assert_eq!(byte_offsets[0], 3_000_000_000_000);
+        assert_eq!(byte_offsets[1], 3 << 40);
+        assert_eq!(byte_offsets[2], 2_000_000_000_000_000);
+        assert_eq!(byte_offsets[3], 2 << 50);
+        assert_eq!(byte_offsets[4], 15_000_000_000_000_000_000);
+        assert_eq!(byte_offsets[5], 15 << 60);
+    }
+
+    #[test]
+    fn bo_overflow() {
+        let bo_overflow = ["16Ei", "19E", "18446744073709551616", "0x10000000000000000"];
+        bo_overflow.iter().for_each(|bo_str| {
+            let bo = ByteOffset::from_str(bo_str);
+            assert!(bo.is_err(), "{} should not parse", bo_str);
+        });
+
+        let max = ByteOffset::from_u64(u64::MAX);
+        let one = ByteOffset::from_u64(1);
+        assert!(max.checked_add(&one).is_err());
+        assert!(one.checked_sub(&max).is_err());
+        assert_eq!(
+            max.checked_sub(&one).unwrap().as_u64().unwrap(),
+            u64::MAX - 1
+        );
+        assert!(ByteOffset::new(u64::MAX, Magnitude::K).as_u64().is_err());
+        assert!(ByteOffset::new(u64::MAX, Magnitude::K) > max);
+    }
+
     #[test]
     fn dec_bad_unit() {
-        let bo_dec = vec!["1Ke", "10B", "100AA", "34Li"];
+        let bo_dec = ["1Ke", "10B", "100AA", "34Li"];
         bo_dec.iter().for_each(|bo_str| {
             let bo = ByteOffset::from_str(bo_str);
             assert!(bo.is_err());
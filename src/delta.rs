@@ -0,0 +1,386 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::error::Result;
+
+/// Identifies a delta file and its format version.
+const MAGIC: &[u8; 8] = b"SCDELTA1";
+
+/// Minimal length of a copy from the source, shorter matches are literals.
+const BLOCK_SIZE: usize = 32;
+
+/// Magic followed by the length and hash of source and target.
+const HEADER_LEN: usize = 8 + 2 * (8 + 32);
+
+const OP_COPY: u8 = 0x01;
+const OP_LITERAL: u8 = 0x02;
+
+/// Length and SHA-256 hash of an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub len: u64,
+    pub hash: [u8; 32],
+}
+
+impl Fingerprint {
+    pub fn of(bytes: &[u8]) -> Self {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(bytes));
+        Self {
+            len: bytes.len() as u64,
+            hash,
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.len.to_le_bytes());
+        out.extend_from_slice(&self.hash);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self> {
+        let mut len = [0u8; 8];
+        len.copy_from_slice(reader.take(8)?);
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(reader.take(32)?);
+        Ok(Self {
+            len: u64::from_le_bytes(len),
+            hash,
+        })
+    }
+}
+
+impl std::fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} bytes, sha256 ", self.len)?;
+        self.hash
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| err!(Format, "Delta is truncated at offset {}", self.pos))?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            let bits = u64::from(byte & 0x7F);
+            if (bits << shift) >> shift != bits {
+                return Err(err!(
+                    Format,
+                    "Length at offset {} exceeds 64 bits",
+                    self.pos - 1
+                ));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(err!(Format, "Malformed length at offset {}", self.pos))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let value = self.varint()?;
+        usize::try_from(value)
+            .map_err(|_| err!(OutOfBounds, "Length {} exceeds the address space", value))
+    }
+}
+
+fn flush_literal(out: &mut Vec<u8>, literal: &mut Vec<u8>) {
+    if !literal.is_empty() {
+        out.push(OP_LITERAL);
+        write_varint(out, literal.len() as u64);
+        out.append(literal);
+    }
+}
+
+/// Create a delta turning `source` into `target`.
+///
+/// The delta starts with the magic `SCDELTA1`, followed by length and
+/// SHA-256 hash of source and target. The remainder is a sequence of
+/// copies from the source (`0x01 <offset> <len>`) and literal bytes
+/// (`0x02 <len> <bytes>`), all numbers LEB128 encoded.
+pub fn create(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    Fingerprint::of(source).write(&mut out);
+    Fingerprint::of(target).write(&mut out);
+
+    // first occurrence of each aligned block of the source
+    let mut blocks = HashMap::new();
+    for (idx, block) in source.chunks_exact(BLOCK_SIZE).enumerate() {
+        blocks.entry(block).or_insert(idx * BLOCK_SIZE);
+    }
+
+    let mut literal = Vec::new();
+    let mut pos = 0;
+    // source offset following the previous copy, preferred for unaligned matches
+    let mut expected = 0;
+    while pos < target.len() {
+        let found = target.get(pos..pos + BLOCK_SIZE).and_then(|block| {
+            if source.get(expected..expected + BLOCK_SIZE) == Some(block) {
+                Some(expected)
+            } else {
+                blocks.get(block).copied()
+            }
+        });
+        let mut from = match found {
+            Some(from) => from,
+            None => {
+                literal.push(target[pos]);
+                pos += 1;
+                continue;
+            }
+        };
+
+        let mut len = BLOCK_SIZE;
+        while from + len < source.len()
+            && pos + len < target.len()
+            && source[from + len] == target[pos + len]
+        {
+            len += 1;
+        }
+        // reclaim trailing literal bytes which match the source as well
+        while from > 0 && literal.last() == Some(&source[from - 1]) {
+            literal.pop();
+            from -= 1;
+            pos -= 1;
+            len += 1;
+        }
+
+        flush_literal(&mut out, &mut literal);
+        out.push(OP_COPY);
+        write_varint(&mut out, from as u64);
+        write_varint(&mut out, len as u64);
+        pos += len;
+        expected = from + len;
+    }
+    flush_literal(&mut out, &mut literal);
+    out
+}
+
+/// Source and target fingerprints stored in a delta.
+pub fn header(delta: &[u8]) -> Result<(Fingerprint, Fingerprint)> {
+    let mut reader = Reader {
+        data: delta,
+        pos: 0,
+    };
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(err!(
+            Format,
+            "Not a delta file, magic {:?} is missing",
+            MAGIC
+        ));
+    }
+    let source = Fingerprint::read(&mut reader)?;
+    let target = Fingerprint::read(&mut reader)?;
+    Ok((source, target))
+}
+
+/// Apply a delta created by [`create`] to `source`.
+///
+/// Refuses a source which is not the one the delta was created from and
+/// verifies the result against the target hash.
+pub fn apply(source: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let (expected_source, expected_target) = header(delta)?;
+    let actual = Fingerprint::of(source);
+    if actual != expected_source {
+        return Err(err!(
+            Format,
+            "Wrong base image, delta expects {} but got {}",
+            expected_source,
+            actual
+        ));
+    }
+
+    let mut reader = Reader {
+        data: delta,
+        pos: HEADER_LEN,
+    };
+    let target_len = usize::try_from(expected_target.len).map_err(|_| {
+        err!(
+            OutOfBounds,
+            "Target of {} bytes exceeds the address space",
+            expected_target.len
+        )
+    })?;
+    let mut target = Vec::new();
+    target.try_reserve_exact(target_len).map_err(|_| {
+        err!(
+            OutOfBounds,
+            "Failed to allocate the target of {} bytes",
+            target_len
+        )
+    })?;
+    // refuse before allocating more than the target needs
+    let check_len = |target: &Vec<u8>, len: usize| {
+        if len > target_len - target.len() {
+            Err(err!(
+                Format,
+                "Result exceeds the target size {}",
+                expected_target.len
+            ))
+        } else {
+            Ok(())
+        }
+    };
+    while reader.pos < delta.len() {
+        match reader.take(1)?[0] {
+            OP_COPY => {
+                let from = reader.usize()?;
+                let len = reader.usize()?;
+                check_len(&target, len)?;
+                let chunk = from
+                    .checked_add(len)
+                    .and_then(|end| source.get(from..end))
+                    .ok_or_else(|| {
+                        err!(
+                            Format,
+                            "Copy of {} bytes at {:#x} exceeds the source",
+                            len,
+                            from
+                        )
+                    })?;
+                target.extend_from_slice(chunk);
+            }
+            OP_LITERAL => {
+                let len = reader.usize()?;
+                check_len(&target, len)?;
+                target.extend_from_slice(reader.take(len)?);
+            }
+            op => {
+                return Err(err!(
+                    Format,
+                    "Unknown operation {:#04x} at offset {}",
+                    op,
+                    reader.pos - 1
+                ))
+            }
+        }
+    }
+
+    let actual = Fingerprint::of(&target);
+    if actual != expected_target {
+        return Err(err!(
+            Format,
+            "Result does not match the target, expected {} but got {}",
+            expected_target,
+            actual
+        ));
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn random(len: usize, seed: u64) -> Vec<u8> {
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+        (0..len).map(|_| rng.gen::<u8>()).collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let source = random(16 * 1024, 1);
+        let mut target = source.clone();
+        // patched bytes, an insertion and a removal
+        target[100..104].copy_from_slice(&[1, 2, 3, 4]);
+        target.splice(5000..5000, random(77, 2));
+        target.drain(9000..9500);
+        target.extend_from_slice(&random(300, 3));
+
+        let delta = create(&source, &target);
+        assert!(delta.len() < 1024, "delta has {} bytes", delta.len());
+        assert_eq!(apply(&source, &delta).expect("Failed to apply"), target);
+    }
+
+    #[test]
+    fn unrelated_and_empty() {
+        let source = random(100, 4);
+        let target = random(50, 5);
+        let delta = create(&source, &target);
+        assert_eq!(apply(&source, &delta).unwrap(), target);
+
+        let delta = create(&[], &target);
+        assert_eq!(apply(&[], &delta).unwrap(), target);
+
+        let delta = create(&source, &[]);
+        assert!(apply(&source, &delta).unwrap().is_empty());
+    }
+
+    #[test]
+    fn wrong_base() {
+        let source = random(1024, 6);
+        let mut target = source.clone();
+        target[10] ^= 0xFF;
+        let delta = create(&source, &target);
+
+        let mut other = source.clone();
+        other[500] ^= 0xFF;
+        assert!(apply(&other, &delta).is_err());
+        assert!(apply(&source, &delta[..delta.len() - 1]).is_err());
+        assert!(apply(&source, b"SCDELTA0").is_err());
+    }
+
+    #[test]
+    fn crafted() {
+        let source = random(32, 7);
+        let header = |target_len: u64| {
+            let mut delta = MAGIC.to_vec();
+            Fingerprint::of(&source).write(&mut delta);
+            Fingerprint {
+                len: target_len,
+                hash: [0; 32],
+            }
+            .write(&mut delta);
+            delta
+        };
+
+        // copies beyond the target size are refused before they are made
+        let mut delta = header(64);
+        for _ in 0..1000 {
+            delta.extend_from_slice(&[OP_COPY, 0, 32]);
+        }
+        let err = apply(&source, &delta).unwrap_err();
+        assert!(
+            err.to_string().contains("exceeds the target size"),
+            "{}",
+            err
+        );
+
+        // the 10th byte of a length carries only the 64th bit
+        let mut delta = header(64);
+        delta.extend_from_slice(&[OP_LITERAL]);
+        delta.extend_from_slice(&[0xFF; 9]);
+        delta.push(0x02);
+        let err = apply(&source, &delta).unwrap_err();
+        assert!(err.to_string().contains("exceeds 64 bits"), "{}", err);
+    }
+}
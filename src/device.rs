@@ -0,0 +1,182 @@
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::byte_offset::ByteOffset;
+use crate::error::{Error, Result};
+use crate::ops::FillPattern;
+use crate::range::Range;
+use crate::sector::SectorMap;
+
+const BUILTIN_DEVICES: &str = include_str!("devices.toml");
+
+/// Flash characteristics of a target device.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceProfile {
+    /// Address the first byte of an image is flashed to.
+    pub flash_base: ByteOffset,
+    pub flash_size: ByteOffset,
+    pub sectors: SectorMap,
+    /// Value of an erased byte.
+    pub erase_value: u8,
+    /// Smallest unit which can be programmed at once.
+    pub write_granularity: ByteOffset,
+}
+
+impl DeviceProfile {
+    /// All built-in profiles by name.
+    pub fn builtin() -> Result<BTreeMap<String, DeviceProfile>> {
+        toml::from_str(BUILTIN_DEVICES)
+            .map_err(|e| err!(Format, "Failed to parse built-in device profiles: {}", e))
+    }
+
+    /// Load a profile file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::Io(format!("Failed to open {:?}", path), e))?;
+        toml::from_str(&content)
+            .map_err(|e| err!(Format, "Failed to parse device profile {:?}: {}", path, e))
+    }
+
+    /// Resolve `device` either as a path to a profile file or as name of a
+    /// built-in profile.
+    pub fn lookup(device: &str) -> Result<Self> {
+        let path = Path::new(device);
+        if path.is_file() {
+            return Self::load(path);
+        }
+        let mut builtin = Self::builtin()?;
+        builtin.remove(&device.to_lowercase()).ok_or_else(|| {
+            err!(
+                Parse,
+                "Unknown device {}, neither a profile file nor one of {}",
+                device,
+                builtin.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+
+    /// Padding which leaves the flash in its erased state.
+    pub fn fill_pattern(&self) -> FillPattern {
+        FillPattern::Byte(self.erase_value)
+    }
+
+    /// Verify an image of `len` bytes fits into the flash.
+    pub fn check_size(&self, len: usize) -> Result<()> {
+        let flash_size = self.flash_size.as_u64()?;
+        if len as u64 > flash_size {
+            Err(err!(
+                OutOfBounds,
+                "Image size {} exceeds the flash size {}",
+                len,
+                flash_size
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Verify `offset` can be programmed, given the write granularity.
+    pub fn check_write_offset(&self, what: &str, offset: &ByteOffset) -> Result<()> {
+        let granularity = self.write_granularity.as_u64()?;
+        let offset = offset.as_u64()?;
+        if granularity == 0 || offset % granularity == 0 {
+            Ok(())
+        } else {
+            Err(err!(
+                Alignment,
+                "{} {:#x} is not a multiple of the write granularity {}",
+                what,
+                offset,
+                granularity
+            ))
+        }
+    }
+
+    /// Address ranges of the sectors overlapping `range`.
+    pub fn sectors_touched(&self, range: &Range) -> Result<Vec<std::ops::Range<u64>>> {
+        let base = self.flash_base.as_u64()?;
+        let start = range.start.as_u64()?;
+        let end = range.end()?.as_u64()?;
+        self.sectors
+            .sectors_between(start, end)
+            .into_iter()
+            .map(|(start, size)| {
+                let start = start.checked_add(base)?;
+                Some(start..start.checked_add(size)?)
+            })
+            .map(|sector| {
+                sector.ok_or_else(|| err!(OutOfBounds, "Sector address exceeds the 64 bit range"))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn builtin_profiles() {
+        let builtin = DeviceProfile::builtin().expect("Failed to parse built-in profiles");
+        assert!(builtin.len() >= 5);
+    }
+
+    #[test]
+    fn lookup() {
+        let profile = DeviceProfile::lookup("STM32F407VG").expect("Failed to find profile");
+        assert_eq!(profile.flash_base.as_u64().unwrap(), 0x0800_0000);
+        assert_eq!(profile.flash_size.as_u64().unwrap(), 1024 * 1024);
+        assert_eq!(profile.fill_pattern(), FillPattern::Byte(0xFF));
+        assert!(profile.check_size(1024 * 1024).is_ok());
+        assert!(profile.check_size(1024 * 1024 + 1).is_err());
+
+        assert!(DeviceProfile::lookup("z80").is_err());
+    }
+
+    #[test]
+    fn profile_file() {
+        let path = std::env::temp_dir().join(format!(
+            "scalpel-device-profile-{}.toml",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            "flash_base = \"0x08000000\"\n\
+             flash_size = 131072\n\
+             sectors = \"2Ki\"\n\
+             erase_value = 0\n\
+             write_granularity = \"8\"\n",
+        )
+        .expect("Failed to write profile");
+
+        let profile = DeviceProfile::lookup(path.to_str().unwrap());
+        std::fs::remove_file(&path).expect("failed to delete tmp file");
+
+        let profile = profile.expect("Failed to load profile");
+        assert_eq!(profile.flash_size.as_u64().unwrap(), 128 * 1024);
+        assert!(profile
+            .check_write_offset("offset", &ByteOffset::from_u64(16))
+            .is_ok());
+        assert!(profile
+            .check_write_offset("offset", &ByteOffset::from_u64(12))
+            .is_err());
+    }
+
+    #[test]
+    fn touched_sectors() {
+        let profile = DeviceProfile::lookup("stm32f407vg").unwrap();
+        let range = Range::from_str("0x8000..0x20001").unwrap();
+        assert_eq!(
+            profile.sectors_touched(&range).unwrap(),
+            vec![
+                0x0800_8000..0x0800_C000,
+                0x0800_C000..0x0801_0000,
+                0x0801_0000..0x0802_0000,
+                0x0802_0000..0x0804_0000
+            ]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Differing byte ranges `[start, end)` of `a` and `b`.
+///
+/// Ranges separated by at most `gap` equal bytes are coalesced. If the
+/// lengths differ, the excess of the longer one is a difference as well.
+pub fn differences(a: &[u8], b: &[u8], gap: usize) -> Vec<Range<usize>> {
+    let common = std::cmp::min(a.len(), b.len());
+    let longest = std::cmp::max(a.len(), b.len());
+
+    let differing = (0..common)
+        .filter(|idx| a[*idx] != b[*idx])
+        .chain(common..longest);
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for idx in differing {
+        match ranges.last_mut() {
+            Some(last) if idx - last.end <= gap => last.end = idx + 1,
+            _ => ranges.push(idx..idx + 1),
+        }
+    }
+    ranges
+}
+
+fn hex_row(bytes: &[u8], row: Range<usize>) -> String {
+    row.map(|idx| match bytes.get(idx) {
+        Some(byte) => format!("{:02x} ", byte),
+        None => "   ".to_owned(),
+    })
+    .collect()
+}
+
+/// Hexdump of `range` of `a` and `b` side by side, 16 bytes per row
+/// starting at a multiple of 16. Differing rows are marked with `*`.
+pub fn hexdump_side_by_side(a: &[u8], b: &[u8], range: &Range<usize>) -> String {
+    const WIDTH: usize = 16;
+    let mut dump = String::new();
+    let mut row_start = range.start - range.start % WIDTH;
+    while row_start < range.end {
+        let row = row_start..row_start + WIDTH;
+        let a_row = hex_row(a, row.clone());
+        let b_row = hex_row(b, row.clone());
+        let marker = if a_row != b_row { '*' } else { ' ' };
+        writeln!(
+            dump,
+            "{:08x} {} {}| {}",
+            row_start,
+            marker,
+            a_row,
+            b_row.trim_end()
+        )
+        .expect("Writing to a String never fails. qed");
+        row_start += WIDTH;
+    }
+    dump
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn coalesce() {
+        let a = [0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let b = [0u8, 9, 2, 3, 4, 9, 9, 7, 9, 0];
+        assert_eq!(differences(&a, &b, 0), vec![1..2, 5..7, 8..10]);
+        assert_eq!(differences(&a, &b, 1), vec![1..2, 5..10]);
+        assert_eq!(differences(&a, &b, 3), vec![1..10]);
+        assert!(differences(&a, &a, 4).is_empty());
+    }
+
+    #[test]
+    fn different_sizes() {
+        let a = [0u8, 1, 2, 3];
+        let b = [0u8, 1, 2, 3, 4, 5];
+        assert_eq!(differences(&a, &b, 0), vec![4..6]);
+        assert_eq!(differences(&b, &a, 0), vec![4..6]);
+
+        let c = [0u8, 1, 9, 3];
+        assert_eq!(differences(&c, &b, 0), vec![2..3, 4..6]);
+        assert_eq!(differences(&c, &b, 1), vec![2..6]);
+    }
+
+    #[test]
+    fn side_by_side() {
+        let a = [0u8; 20];
+        let mut b = [0u8; 18];
+        b[17] = 0xFF;
+        let dump = hexdump_side_by_side(&a, &b, &(17..20));
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("00000010 * 00 00 00 00 "));
+        assert!(lines[0].ends_with("| 00 ff"));
+    }
+}
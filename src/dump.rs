@@ -0,0 +1,171 @@
+use std::fmt::Write;
+
+use crate::error::Result;
+use crate::ops::Endianness;
+
+/// Layout of a hexdump line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpFormat {
+    /// Bytes per line.
+    pub width: usize,
+    /// Bytes per group, a group is printed as one word.
+    pub group: usize,
+    /// Byte order of a group, big endian prints the bytes in memory order.
+    pub endianness: Endianness,
+    /// Replace repeated lines by a single `*`.
+    pub collapse: bool,
+}
+
+impl Default for DumpFormat {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            group: 1,
+            endianness: Endianness::Big,
+            collapse: true,
+        }
+    }
+}
+
+impl DumpFormat {
+    fn check(&self) -> Result<()> {
+        if self.width == 0 || self.group == 0 || self.width % self.group != 0 {
+            Err(err!(
+                Parse,
+                "Width {} must be a non zero multiple of the group size {}",
+                self.width,
+                self.group
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn line(&self, dump: &mut String, address: u64, line: &[u8]) {
+        write!(dump, "{:08x} ", address).expect("Writing to a String never fails. qed");
+        for group in 0..self.width / self.group {
+            let start = group * self.group;
+            let end = std::cmp::min(start + self.group, line.len());
+            let mut word: Vec<u8> = line.get(start..end).unwrap_or(&[]).to_vec();
+            if self.endianness == Endianness::Little {
+                word.reverse();
+            }
+            dump.push(' ');
+            for byte in word.iter() {
+                write!(dump, "{:02x}", byte).expect("Writing to a String never fails. qed");
+            }
+            (word.len()..self.group).for_each(|_| dump.push_str("  "));
+        }
+        dump.push_str("  |");
+        dump.extend(line.iter().map(|byte| match byte {
+            0x20..=0x7E => *byte as char,
+            _ => '.',
+        }));
+        dump.push_str("|\n");
+    }
+}
+
+/// Canonical hexdump of `bytes`, the first byte is at `address`.
+///
+/// Every line starts with its address, followed by the groups in hex and
+/// the printable ASCII characters. The last line holds the address
+/// following the dumped bytes.
+pub fn hexdump(bytes: &[u8], address: u64, format: &DumpFormat) -> Result<String> {
+    format.check()?;
+    // all line addresses are below the end
+    let end = address.checked_add(bytes.len() as u64).ok_or_else(|| {
+        err!(
+            OutOfBounds,
+            "Dump of {} bytes at {:#x} exceeds the 64 bit range",
+            bytes.len(),
+            address
+        )
+    })?;
+
+    let mut dump = String::new();
+    let mut previous: Option<&[u8]> = None;
+    let mut collapsed = false;
+    for (idx, line) in bytes.chunks(format.width).enumerate() {
+        let repeated = previous == Some(line) && line.len() == format.width;
+        previous = Some(line);
+        if format.collapse && repeated {
+            if !collapsed {
+                dump.push_str("*\n");
+                collapsed = true;
+            }
+            continue;
+        }
+        collapsed = false;
+        format.line(&mut dump, address + (idx * format.width) as u64, line);
+    }
+    writeln!(dump, "{:08x}", end).expect("Writing to a String never fails. qed");
+    Ok(dump)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn canonical() {
+        let bytes = b"Hello, scalpel!\x00\x01\x02";
+        let dump = hexdump(&bytes[..], 0x0800_0000, &DumpFormat::default()).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            lines[0],
+            "08000000  48 65 6c 6c 6f 2c 20 73 63 61 6c 70 65 6c 21 00  |Hello, scalpel!.|"
+        );
+        assert_eq!(
+            lines[1],
+            "08000010  01 02                                            |..|"
+        );
+        assert_eq!(lines[2], "08000012");
+    }
+
+    #[test]
+    fn grouped_words() {
+        let bytes = [0x01u8, 0x02, 0x03, 0x04, 0x05, 0x06];
+        let mut format = DumpFormat {
+            width: 8,
+            group: 4,
+            ..Default::default()
+        };
+        let dump = hexdump(&bytes, 0, &format).unwrap();
+        assert!(dump.starts_with("00000000  01020304 0506      |"));
+
+        format.endianness = Endianness::Little;
+        let dump = hexdump(&bytes, 0, &format).unwrap();
+        assert!(dump.starts_with("00000000  04030201 0605      |"));
+
+        format.group = 3;
+        assert!(hexdump(&bytes, 0, &format).is_err());
+    }
+
+    #[test]
+    fn address_overflow() {
+        let bytes = [0u8; 32];
+        assert!(hexdump(&bytes, u64::MAX - 0x0F, &DumpFormat::default()).is_err());
+        // the address following the dumped bytes is printed as well
+        assert!(hexdump(&bytes[..16], u64::MAX - 0x0F, &DumpFormat::default()).is_err());
+        let dump = hexdump(&bytes[..15], u64::MAX - 0x0F, &DumpFormat::default()).unwrap();
+        assert!(dump.starts_with("fffffffffffffff0 "));
+        assert!(dump.ends_with("\nffffffffffffffff\n"));
+    }
+
+    #[test]
+    fn collapse() {
+        let bytes = [0xFFu8; 64];
+        let dump = hexdump(&bytes, 0x100, &DumpFormat::default()).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00000100  ff ff"));
+        assert_eq!(lines[1], "*");
+        assert_eq!(lines[2], "00000140");
+
+        let format = DumpFormat {
+            collapse: false,
+            ..Default::default()
+        };
+        assert_eq!(hexdump(&bytes, 0, &format).unwrap().lines().count(), 5);
+    }
+}
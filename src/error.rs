@@ -0,0 +1,71 @@
+use std::fmt;
+use std::io;
+use std::num::TryFromIntError;
+
+/// Creates an `Error` of the given variant from a format string.
+macro_rules! err {
+    ($kind:ident, $($arg:tt)*) => {
+        $crate::error::Error::$kind(format!($($arg)*))
+    };
+}
+
+pub type Result<X> = std::result::Result<X, Error>;
+
+/// Errors of scalpel, the variant tells what went wrong while the
+/// message tells where.
+#[derive(Debug)]
+pub enum Error {
+    /// A malformed argument such as a byte offset, range, pattern or value.
+    Parse(String),
+    /// An offset or range beyond the image, the device or the 64 bit range.
+    OutOfBounds(String),
+    /// An offset or size off a sector, word or write granularity boundary.
+    Alignment(String),
+    /// Content of an input that is malformed or of an unsupported format.
+    Format(String),
+    /// Reading or writing a file failed.
+    Io(String, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(message)
+            | Error::OutOfBounds(message)
+            | Error::Alignment(message)
+            | Error::Format(message) => write!(f, "{}", message),
+            Error::Io(message, e) => write!(f, "{}: {}", message, e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(_: TryFromIntError) -> Self {
+        err!(OutOfBounds, "Offset exceeds the platform address space")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let e = err!(OutOfBounds, "Range {} exceeds {}", 3, 2);
+        assert_eq!(e.to_string(), "Range 3 exceeds 2");
+        let e = Error::Io(
+            "Failed to read \"a.bin\"".to_owned(),
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert_eq!(e.to_string(), "Failed to read \"a.bin\": not found");
+    }
+}
@@ -0,0 +1,390 @@
+//! Arithmetic expressions for byte offsets.
+//!
+//! Grammar, with the usual precedence of `*` and `/` over `+` and `-`:
+//!
+//! ```text
+//! expr   := term (('+' | '-') term)*
+//! term   := factor (('*' | '/') factor)*
+//! factor := literal | '(' expr ')' | ident '(' expr (',' expr)* ')'
+//! ```
+//!
+//! Literals are either hexadecimal (`0x` prefix) or decimal with an optional
+//! magnitude suffix (`4Ki`, `12M`). Supported functions are
+//! `align_up(x, alignment)` and `align_down(x, alignment)`.
+//! All arithmetic is checked, overflows, underflows and divisions by zero
+//! are reported as errors.
+
+use crate::byte_offset::{ByteOffset, Magnitude};
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(ByteOffset),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Open,
+    Close,
+    Comma,
+}
+
+/// Split `expr` into tokens, each with the position it starts at.
+fn tokenize(expr: &str) -> Result<Vec<(usize, Token)>> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0usize;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        let start = idx;
+        let token = match c {
+            ' ' | '\t' => {
+                idx += 1;
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '0'..='9' => {
+                let hex = c == '0' && chars.get(idx + 1).is_some_and(|x| *x == 'x' || *x == 'X');
+                if hex {
+                    idx += 2;
+                    let digits_start = idx;
+                    while idx < chars.len() && chars[idx].is_ascii_hexdigit() {
+                        idx += 1;
+                    }
+                    let digits: String = chars[digits_start..idx].iter().collect();
+                    if digits.is_empty() {
+                        return Err(err!(Parse, "Missing hex digits at {} in {}", start, expr));
+                    }
+                    if idx < chars.len() && chars[idx].is_ascii_alphanumeric() {
+                        return Err(err!(
+                            Parse,
+                            "Unexpected character {} at {} in {}",
+                            chars[idx],
+                            idx,
+                            expr
+                        ));
+                    }
+                    let num = u64::from_str_radix(&digits, 16)
+                        .map_err(|e| err!(Parse, "Failed to parse u64 from hex {}", e))?;
+                    tokens.push((start, Token::Literal(ByteOffset::new(num, Magnitude::Unit))));
+                } else {
+                    while idx < chars.len() && chars[idx].is_ascii_digit() {
+                        idx += 1;
+                    }
+                    let digits: String = chars[start..idx].iter().collect();
+                    let suffix_start = idx;
+                    while idx < chars.len() && chars[idx].is_ascii_alphanumeric() {
+                        idx += 1;
+                    }
+                    let suffix: String = chars[suffix_start..idx].iter().collect();
+                    let num = digits
+                        .parse::<u64>()
+                        .map_err(|e| err!(Parse, "Failed to parse u64 {}", e))?;
+                    let magnitude = Magnitude::parse(&suffix)
+                        .map_err(|e| err!(Parse, "Failed to parse magnitude {}", e))?;
+                    tokens.push((start, Token::Literal(ByteOffset::new(num, magnitude))));
+                }
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                while idx < chars.len() && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '_')
+                {
+                    idx += 1;
+                }
+                let ident: String = chars[start..idx].iter().collect();
+                tokens.push((start, Token::Ident(ident)));
+                continue;
+            }
+            c => {
+                return Err(err!(
+                    Parse,
+                    "Unexpected character {} at {} in {}",
+                    c,
+                    start,
+                    expr
+                ));
+            }
+        };
+        tokens.push((start, token));
+        idx += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    expr: &'a str,
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error_here(&self, what: &str) -> Error {
+        match self.tokens.get(self.pos) {
+            Some((at, _)) => err!(Parse, "{} at {} in {}", what, at, self.expr),
+            None => err!(Parse, "{} at end of {}", what, self.expr),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        if self.peek() == Some(&expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error_here(&format!("Expected {:?}", expected)))
+        }
+    }
+
+    fn expr(&mut self) -> Result<ByteOffset> {
+        let mut lhs = self.term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.pos += 1;
+                    lhs = lhs.checked_add(&self.term()?)?;
+                }
+                Some(Token::Minus) => {
+                    self.pos += 1;
+                    lhs = lhs.checked_sub(&self.term()?)?;
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<ByteOffset> {
+        let mut lhs = self.factor()?;
+        loop {
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.pos += 1;
+                    let rhs = self.factor()?;
+                    let num = lhs.as_u64()?.checked_mul(rhs.as_u64()?).ok_or_else(|| {
+                        err!(
+                            OutOfBounds,
+                            "Product of {} and {} exceeds the 64 bit range",
+                            lhs,
+                            rhs
+                        )
+                    })?;
+                    lhs = ByteOffset::from_u64(num);
+                }
+                Some(Token::Slash) => {
+                    self.pos += 1;
+                    let rhs = self.factor()?;
+                    let num = lhs
+                        .as_u64()?
+                        .checked_div(rhs.as_u64()?)
+                        .ok_or_else(|| err!(Parse, "Division of {} by zero", lhs))?;
+                    lhs = ByteOffset::from_u64(num);
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Result<ByteOffset> {
+        match self.next() {
+            Some(Token::Literal(literal)) => Ok(literal),
+            Some(Token::Open) => {
+                let inner = self.expr()?;
+                self.expect(Token::Close)?;
+                Ok(inner)
+            }
+            Some(Token::Ident(ident)) => {
+                self.expect(Token::Open)?;
+                let mut args = vec![self.expr()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    args.push(self.expr()?);
+                }
+                self.expect(Token::Close)?;
+                call(&ident, &args)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.error_here("Expected a number, ( or function"))
+            }
+        }
+    }
+}
+
+/// Evaluate a builtin function.
+fn call(ident: &str, args: &[ByteOffset]) -> Result<ByteOffset> {
+    match (ident, args) {
+        ("align_up", [value, alignment]) => {
+            let value = value.as_u64()?;
+            let alignment = alignment_of(alignment)?;
+            let aligned = match value % alignment {
+                0 => Some(value),
+                rem => value.checked_add(alignment - rem),
+            };
+            aligned.map(ByteOffset::from_u64).ok_or_else(|| {
+                err!(
+                    OutOfBounds,
+                    "Aligning {} up to {} exceeds the 64 bit range",
+                    value,
+                    alignment
+                )
+            })
+        }
+        ("align_down", [value, alignment]) => {
+            let value = value.as_u64()?;
+            let alignment = alignment_of(alignment)?;
+            Ok(ByteOffset::from_u64(value - value % alignment))
+        }
+        ("align_up", _) | ("align_down", _) => Err(err!(
+            Parse,
+            "{} expects 2 arguments, got {}",
+            ident,
+            args.len()
+        )),
+        _ => Err(err!(Parse, "Unknown function {}", ident)),
+    }
+}
+
+fn alignment_of(alignment: &ByteOffset) -> Result<u64> {
+    match alignment.as_u64()? {
+        0 => Err(err!(Parse, "Alignment must not be zero")),
+        alignment => Ok(alignment),
+    }
+}
+
+/// Evaluate `expr` to a byte offset.
+///
+/// A plain literal keeps its magnitude, anything computed is returned in
+/// bytes.
+pub fn evaluate(expr: &str) -> Result<ByteOffset> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Err(err!(Parse, "Empty expression"));
+    }
+    let mut parser = Parser {
+        expr,
+        tokens,
+        pos: 0,
+    };
+    let value = parser.expr()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error_here("Unexpected trailing input"));
+    }
+    // reject values which are not representable
+    value.as_u64()?;
+    Ok(value)
+}
+
+/// Split `expr` at the first occurence of `separator` which is not
+/// enclosed by parentheses.
+pub fn split_top_level<'a>(expr: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+    let mut depth = 0i32;
+    for (idx, c) in expr.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 && expr[idx..].starts_with(separator) => {
+                return Some((&expr[..idx], &expr[idx + separator.len()..]));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(expr: &str) -> u64 {
+        evaluate(expr)
+            .expect("Failed to evaluate")
+            .as_u64()
+            .unwrap()
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(evaluate("4Ki").unwrap(), ByteOffset::new(4, Magnitude::Ki));
+        assert_eq!(
+            evaluate("0x100").unwrap(),
+            ByteOffset::new(256, Magnitude::Unit)
+        );
+        assert_eq!(
+            evaluate(" 12 ").unwrap(),
+            ByteOffset::new(12, Magnitude::Unit)
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("0x08000000+64Ki-0x200"), 0x0800_0000 + 65536 - 0x200);
+        assert_eq!(eval("(16Ki*3)+4Ki"), 16 * 1024 * 3 + 4096);
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("10 - 2 - 3"), 5);
+        assert_eq!(eval("1Mi / 4Ki"), 256);
+        assert_eq!(eval("7 / 2"), 3);
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(eval("align_up(0x1001, 4Ki)"), 0x2000);
+        assert_eq!(eval("align_up(0x1000, 4Ki)"), 0x1000);
+        assert_eq!(eval("align_down(0x1fff, 4Ki)"), 0x1000);
+        assert_eq!(eval("align_up(10, 3)"), 12);
+        assert_eq!(eval("0x08000000 + align_up(1234, 0x200)"), 0x0800_0600);
+    }
+
+    #[test]
+    fn errors() {
+        let bad = [
+            "",
+            "1 +",
+            "(1 + 2",
+            "1 + 2)",
+            "1 - 2",
+            "1 / 0",
+            "0x",
+            "0x20Fg",
+            "1Ke",
+            "align_up(1)",
+            "align_up(1, 0)",
+            "align_up(0xFFFFFFFFFFFFFFFF, 2)",
+            "unknown(1, 2)",
+            "16Ei",
+            "8Ei * 2",
+            "0xFFFFFFFFFFFFFFFF + 1",
+            "1 $ 2",
+        ];
+        bad.iter().for_each(|expr| {
+            assert!(evaluate(expr).is_err(), "{} should not evaluate", expr);
+        });
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_top_level("(1+2)+3", "+"), Some(("(1+2)", "3")));
+        assert_eq!(split_top_level("1+2+3", "+"), Some(("1", "2+3")));
+        assert_eq!(split_top_level("(1+2)", "+"), None);
+        assert_eq!(
+            split_top_level("align_up(1,2)..4Ki", ".."),
+            Some(("align_up(1,2)", "4Ki"))
+        );
+    }
+}
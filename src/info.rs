@@ -0,0 +1,670 @@
+use goblin::elf::Elf;
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+
+use crate::error::Result;
+
+/// Minimal length of a run of `0xFF` or `0x00` to be reported as erased.
+const ERASED_MIN_LEN: usize = 64;
+
+/// Bits per byte above which a block likely holds compressed or encrypted
+/// data.
+const HIGH_ENTROPY: f64 = 7.0;
+
+const UIMAGE_MAGIC: u32 = 0x2705_1956;
+const UIMAGE_HEADER_LEN: usize = 64;
+const MCUBOOT_MAGIC: u32 = 0x96f3_b83d;
+const MCUBOOT_HEADER_LEN: usize = 32;
+
+/// Address range `[start, end)`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl From<Range<u64>> for Span {
+    fn from(range: Range<u64>) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#010x}..{:#010x} ({} bytes)",
+            self.start,
+            self.end,
+            self.end - self.start
+        )
+    }
+}
+
+/// A run of a single erase value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Erased {
+    #[serde(flatten)]
+    pub span: Span,
+    pub value: u8,
+}
+
+/// Well-known header found in the image, `offset` is relative to the first
+/// byte.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Header {
+    UImage {
+        offset: u64,
+        name: String,
+        load_address: u32,
+        entry_point: u32,
+        data_size: u32,
+        header_crc_ok: bool,
+        data_crc_ok: bool,
+    },
+    McuBoot {
+        offset: u64,
+        load_address: u32,
+        header_size: u16,
+        image_size: u32,
+        version: String,
+    },
+    VectorTable {
+        offset: u64,
+        initial_sp: u32,
+        reset_handler: u32,
+    },
+    Elf {
+        offset: u64,
+        class: u8,
+        machine: u16,
+        entry: u64,
+    },
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Header::UImage {
+                offset,
+                name,
+                load_address,
+                entry_point,
+                data_size,
+                header_crc_ok,
+                data_crc_ok,
+            } => write!(
+                f,
+                "uImage at {:#x}: {:?}, load {:#010x}, entry {:#010x}, {} data bytes, header CRC {}, data CRC {}",
+                offset,
+                name,
+                load_address,
+                entry_point,
+                data_size,
+                if *header_crc_ok { "ok" } else { "bad" },
+                if *data_crc_ok { "ok" } else { "bad" }
+            ),
+            Header::McuBoot {
+                offset,
+                load_address,
+                header_size,
+                image_size,
+                version,
+            } => write!(
+                f,
+                "MCUboot at {:#x}: version {}, load {:#010x}, header {} bytes, image {} bytes",
+                offset, version, load_address, header_size, image_size
+            ),
+            Header::VectorTable {
+                offset,
+                initial_sp,
+                reset_handler,
+            } => write!(
+                f,
+                "ARM vector table at {:#x}: initial SP {:#010x}, reset handler {:#010x}",
+                offset, initial_sp, reset_handler
+            ),
+            Header::Elf {
+                offset,
+                class,
+                machine,
+                entry,
+            } => write!(
+                f,
+                "ELF{} at {:#x}: machine {}, entry {:#x}",
+                class, offset, machine, entry
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Checksums {
+    /// CRC-32 (IEEE 802.3) as used by zlib.
+    pub crc32: u32,
+    /// Wrapping sum of all bytes.
+    pub sum32: u32,
+    pub sha256: String,
+}
+
+/// Entropy of all blocks, in bits per byte.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntropySummary {
+    pub min: f64,
+    pub max: f64,
+    /// Mean weighted by the block sizes.
+    pub mean: f64,
+}
+
+/// Summary of an image.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Info {
+    pub format: String,
+    /// How the format was determined.
+    pub detected_by: String,
+    pub size: u64,
+    /// Bytes within the segments.
+    pub data_size: u64,
+    pub base_address: Option<u64>,
+    pub segments: Vec<Span>,
+    pub holes: Vec<Span>,
+    pub erased: Vec<Erased>,
+    pub entropy_block_size: u64,
+    /// Shannon entropy in bits per byte of each block, the blocks of each
+    /// segment start at the segment, holes are skipped.
+    pub entropy: Vec<f64>,
+    pub entropy_summary: Option<EntropySummary>,
+    /// Ranges of consecutive blocks above `HIGH_ENTROPY` bits per byte.
+    pub high_entropy: Vec<Span>,
+    pub headers: Vec<Header>,
+    pub checksums: Checksums,
+}
+
+/// SHA-256 of `bytes` as lowercase hex.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn u16_le(bytes: &[u8], offset: usize) -> Option<u16> {
+    let mut word = [0u8; 2];
+    word.copy_from_slice(bytes.get(offset..offset + 2)?);
+    Some(u16::from_le_bytes(word))
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(bytes.get(offset..offset + 4)?);
+    Some(u32::from_le_bytes(word))
+}
+
+fn u32_be(bytes: &[u8], offset: usize) -> Option<u32> {
+    u32_le(bytes, offset).map(u32::swap_bytes)
+}
+
+fn entropy(block: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    block.iter().for_each(|byte| counts[*byte as usize] += 1);
+    let len = block.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+/// Runs of at least `ERASED_MIN_LEN` bytes of `0xFF` or `0x00`, `address`
+/// is the address of the first byte.
+fn erased_runs(bytes: &[u8], address: u64) -> Vec<Erased> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let value = bytes[start];
+        let len = bytes[start..]
+            .iter()
+            .take_while(|byte| **byte == value)
+            .count();
+        if (value == 0xFF || value == 0x00) && len >= ERASED_MIN_LEN {
+            runs.push(Erased {
+                span: Span::from(address + start as u64..address + (start + len) as u64),
+                value,
+            });
+        }
+        start += len;
+    }
+    runs
+}
+
+fn uimage(bytes: &[u8]) -> Option<Header> {
+    if u32_be(bytes, 0)? != UIMAGE_MAGIC || bytes.len() < UIMAGE_HEADER_LEN {
+        return None;
+    }
+    let mut header = bytes[..UIMAGE_HEADER_LEN].to_vec();
+    header[4..8].copy_from_slice(&[0u8; 4]);
+    let data_size = u32_be(bytes, 12)?;
+    let data = bytes.get(UIMAGE_HEADER_LEN..UIMAGE_HEADER_LEN + data_size as usize);
+    let name = &bytes[32..UIMAGE_HEADER_LEN];
+    let name = name.split(|byte| *byte == 0).next().unwrap_or(&[]);
+    Some(Header::UImage {
+        offset: 0,
+        name: String::from_utf8_lossy(name).into_owned(),
+        load_address: u32_be(bytes, 16)?,
+        entry_point: u32_be(bytes, 20)?,
+        data_size,
+        header_crc_ok: crc32fast::hash(&header) == u32_be(bytes, 4)?,
+        data_crc_ok: data.map(crc32fast::hash) == u32_be(bytes, 24),
+    })
+}
+
+fn mcuboot(bytes: &[u8]) -> Option<Header> {
+    if u32_le(bytes, 0)? != MCUBOOT_MAGIC || bytes.len() < MCUBOOT_HEADER_LEN {
+        return None;
+    }
+    Some(Header::McuBoot {
+        offset: 0,
+        load_address: u32_le(bytes, 4)?,
+        header_size: u16_le(bytes, 8)?,
+        image_size: u32_le(bytes, 12)?,
+        version: format!(
+            "{}.{}.{}+{}",
+            bytes[20],
+            bytes[21],
+            u16_le(bytes, 22)?,
+            u32_le(bytes, 24)?
+        ),
+    })
+}
+
+/// A Cortex-M vector table starts with the initial stack pointer, which
+/// points into RAM, followed by the thumb address of the reset handler.
+fn vector_table(bytes: &[u8], offset: usize) -> Option<Header> {
+    let initial_sp = u32_le(bytes, offset)?;
+    let reset_handler = u32_le(bytes, offset + 4)?;
+    let plausible_sp = (0x1000_0000..=0x4000_0000).contains(&initial_sp) && initial_sp % 4 == 0;
+    let plausible_reset = reset_handler & 1 == 1 && reset_handler < 0x4000_0000;
+    if plausible_sp && plausible_reset {
+        Some(Header::VectorTable {
+            offset: offset as u64,
+            initial_sp,
+            reset_handler,
+        })
+    } else {
+        None
+    }
+}
+
+fn elf(bytes: &[u8]) -> Option<Header> {
+    if !bytes.starts_with(b"\x7fELF") {
+        return None;
+    }
+    let elf = Elf::parse(bytes).ok()?;
+    Some(Header::Elf {
+        offset: 0,
+        class: if elf.is_64 { 64 } else { 32 },
+        machine: elf.header.e_machine,
+        entry: elf.entry,
+    })
+}
+
+fn headers(bytes: &[u8]) -> Vec<Header> {
+    if let Some(header) = elf(bytes) {
+        return vec![header];
+    }
+    let mut headers = Vec::new();
+    let payload = if let Some(header) = uimage(bytes) {
+        headers.push(header);
+        UIMAGE_HEADER_LEN
+    } else if let Some(header) = mcuboot(bytes) {
+        let header_size = match &header {
+            Header::McuBoot { header_size, .. } => *header_size as usize,
+            _ => unreachable!("Constructed above. qed"),
+        };
+        headers.push(header);
+        header_size
+    } else {
+        0
+    };
+    headers.extend(vector_table(bytes, payload));
+    headers
+}
+
+/// The bytes of `segment` within `bytes` starting at `address`.
+fn segment_bytes<'a>(bytes: &'a [u8], address: u64, segment: &Range<u64>) -> Result<&'a [u8]> {
+    let offset = |at: u64| usize::try_from(at.checked_sub(address)?).ok();
+    offset(segment.start)
+        .zip(offset(segment.end))
+        .and_then(|(start, end)| bytes.get(start..end))
+        .ok_or_else(|| {
+            err!(
+                OutOfBounds,
+                "Segment {:#x}..{:#x} is not within the {} bytes at {:#x}",
+                segment.start,
+                segment.end,
+                bytes.len(),
+                address
+            )
+        })
+}
+
+/// Summarize `bytes`, the first byte is at `base`.
+///
+/// `segments` are the ascending absolute address ranges holding data, the
+/// gaps between them are reported as holes. Erased regions and entropy are
+/// only determined within the segments.
+pub fn inspect(
+    format: &str,
+    detected_by: &str,
+    bytes: &[u8],
+    base: Option<u64>,
+    segments: &[Range<u64>],
+    block_size: usize,
+) -> Result<Info> {
+    let address = base.unwrap_or(0);
+    if let Some(pair) = segments.windows(2).find(|pair| pair[0].end > pair[1].start) {
+        return Err(err!(
+            OutOfBounds,
+            "Segments {:#x}..{:#x} and {:#x}..{:#x} are not ascending",
+            pair[0].start,
+            pair[0].end,
+            pair[1].start,
+            pair[1].end
+        ));
+    }
+    let segment_bytes = segments
+        .iter()
+        .map(|segment| segment_bytes(bytes, address, segment))
+        .collect::<Result<Vec<_>>>()?;
+    let holes = segments
+        .windows(2)
+        .map(|pair| Span::from(pair[0].end..pair[1].start))
+        .collect();
+    let erased = segments
+        .iter()
+        .zip(segment_bytes.iter())
+        .flat_map(|(segment, bytes)| erased_runs(bytes, segment.start))
+        .collect();
+
+    let block_size = block_size.max(1);
+    let mut entropy_blocks = Vec::new();
+    let mut high_entropy: Vec<Span> = Vec::new();
+    let mut weighted = 0.0;
+    for (segment, bytes) in segments.iter().zip(segment_bytes.iter()) {
+        for (idx, block) in bytes.chunks(block_size).enumerate() {
+            let bits = entropy(block);
+            let start = segment.start + (idx * block_size) as u64;
+            let end = start + block.len() as u64;
+            if bits > HIGH_ENTROPY {
+                match high_entropy.last_mut() {
+                    Some(last) if last.end == start => last.end = end,
+                    _ => high_entropy.push(Span::from(start..end)),
+                }
+            }
+            weighted += bits * block.len() as f64;
+            entropy_blocks.push(bits);
+        }
+    }
+    let data_size: u64 = segments
+        .iter()
+        .map(|segment| segment.end - segment.start)
+        .sum();
+    let entropy_summary = if entropy_blocks.is_empty() {
+        None
+    } else {
+        Some(EntropySummary {
+            min: entropy_blocks.iter().cloned().fold(f64::INFINITY, f64::min),
+            max: entropy_blocks
+                .iter()
+                .cloned()
+                .fold(f64::NEG_INFINITY, f64::max),
+            mean: weighted / data_size as f64,
+        })
+    };
+
+    Ok(Info {
+        format: format.to_owned(),
+        detected_by: detected_by.to_owned(),
+        size: bytes.len() as u64,
+        data_size,
+        base_address: base,
+        segments: segments.iter().cloned().map(Span::from).collect(),
+        holes,
+        erased,
+        entropy_block_size: block_size as u64,
+        entropy: entropy_blocks,
+        entropy_summary,
+        high_entropy,
+        headers: headers(bytes),
+        checksums: Checksums {
+            crc32: crc32fast::hash(bytes),
+            sum32: bytes
+                .iter()
+                .fold(0u32, |sum, byte| sum.wrapping_add(u32::from(*byte))),
+            sha256: sha256_hex(bytes),
+        },
+    })
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn list<T: fmt::Display>(f: &mut fmt::Formatter, title: &str, items: &[T]) -> fmt::Result {
+            match items.split_first() {
+                None => writeln!(f, "{:<12}none", title),
+                Some((first, rest)) => {
+                    writeln!(f, "{:<12}{}", title, first)?;
+                    rest.iter()
+                        .try_for_each(|item| writeln!(f, "{:<12}{}", "", item))
+                }
+            }
+        }
+
+        writeln!(
+            f,
+            "{:<12}{} (detected by {})",
+            "Format:", self.format, self.detected_by
+        )?;
+        if self.data_size == self.size {
+            writeln!(f, "{:<12}{} bytes", "Size:", self.size)?;
+        } else {
+            writeln!(
+                f,
+                "{:<12}{} bytes, {} bytes of data",
+                "Size:", self.size, self.data_size
+            )?;
+        }
+        if let Some(base) = self.base_address {
+            writeln!(f, "{:<12}{:#010x}", "Base:", base)?;
+        }
+        list(f, "Segments:", &self.segments)?;
+        list(f, "Holes:", &self.holes)?;
+        let erased: Vec<String> = self
+            .erased
+            .iter()
+            .map(|erased| format!("{} of {:#04x}", erased.span, erased.value))
+            .collect();
+        list(f, "Erased:", &erased)?;
+        match &self.entropy_summary {
+            None => writeln!(f, "{:<12}none", "Entropy:")?,
+            Some(summary) => writeln!(
+                f,
+                "{:<12}bits per byte of {} byte blocks: min {:.2}, max {:.2}, mean {:.2}",
+                "Entropy:", self.entropy_block_size, summary.min, summary.max, summary.mean
+            )?,
+        }
+        self.high_entropy
+            .iter()
+            .try_for_each(|span| writeln!(f, "{:<12}above {:.1} in {}", "", HIGH_ENTROPY, span))?;
+        list(f, "Headers:", &self.headers)?;
+        writeln!(
+            f,
+            "{:<12}CRC-32 {:#010x}, sum32 {:#010x}, SHA-256 {}",
+            "Checksums:", self.checksums.crc32, self.checksums.sum32, self.checksums.sha256
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn segments_holes_erased() {
+        let mut bytes = vec![0xFFu8; 0x300];
+        bytes[0] = 0x01;
+        bytes[0x1FF] = 0x02;
+        let segments = [0x1000..0x1200, 0x1280..0x1300];
+        let info = inspect(
+            "IntelHex",
+            "--input-format",
+            &bytes,
+            Some(0x1000),
+            &segments,
+            0x100,
+        )
+        .unwrap();
+
+        assert_eq!(info.holes, vec![Span::from(0x1200..0x1280)]);
+        // the hole is not reported as erased, the segment after it is
+        assert_eq!(
+            info.erased,
+            vec![
+                Erased {
+                    span: Span::from(0x1001..0x11FF),
+                    value: 0xFF
+                },
+                Erased {
+                    span: Span::from(0x1280..0x1300),
+                    value: 0xFF
+                }
+            ]
+        );
+        assert_eq!(info.entropy.len(), 3);
+        assert_eq!(info.data_size, 0x280);
+    }
+
+    #[test]
+    fn bad_segments() {
+        let bytes = [0u8; 0x100];
+        let inspect = |segments: &[Range<u64>]| {
+            inspect(
+                "Bin",
+                "--input-format",
+                &bytes,
+                Some(0x1000),
+                segments,
+                0x10,
+            )
+        };
+        assert!(inspect(&[0x1000..0x1010, 0x1080..0x1100]).is_ok());
+        // below the base, beyond the end and not ascending
+        assert!(inspect(&[0x0F00..0x1010, 0x1080..0x1100]).is_err());
+        assert!(inspect(&[0x1000..0x1010, 0x1080..0x1101]).is_err());
+        assert!(inspect(&[0x1080..0x1100, 0x1000..0x1010]).is_err());
+    }
+
+    #[test]
+    fn entropy_summary() {
+        // erased, random and again erased blocks with a hole in between
+        let mut bytes = vec![0xFFu8; 0x400];
+        let mut state = 1u32;
+        for byte in bytes[0x100..0x300].iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            *byte = (state >> 16) as u8;
+        }
+        let segments = [0..0x300, 0x380..0x400];
+        let info = inspect("Bin", "--input-format", &bytes, None, &segments, 0x100).unwrap();
+
+        assert_eq!(info.entropy.len(), 4);
+        assert_eq!(info.high_entropy, vec![Span::from(0x100..0x300)]);
+        let summary = info.entropy_summary.clone().unwrap();
+        assert_eq!(summary.min, 0.0);
+        assert!(summary.max > HIGH_ENTROPY);
+        assert!(summary.mean > 3.0 && summary.mean < summary.max);
+
+        let text = info.to_string();
+        assert!(text.contains("bytes of data"), "{}", text);
+        assert!(text.lines().all(|line| line.len() < 200), "{}", text);
+        assert!(
+            text.contains("above 7.0 in 0x00000100..0x00000300"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn block_entropy() {
+        assert_eq!(entropy(&[0u8; 128]).to_string(), "0");
+        let all: Vec<u8> = (0..=255).collect();
+        assert!((entropy(&all) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mcuboot_vector_table() {
+        let mut bytes = vec![0u8; 0x300];
+        bytes[0..4].copy_from_slice(&MCUBOOT_MAGIC.to_le_bytes());
+        bytes[8..10].copy_from_slice(&0x200u16.to_le_bytes());
+        bytes[12..16].copy_from_slice(&0x100u32.to_le_bytes());
+        bytes[20] = 1;
+        bytes[21] = 2;
+        bytes[22..24].copy_from_slice(&3u16.to_le_bytes());
+        bytes[0x200..0x204].copy_from_slice(&0x2002_0000u32.to_le_bytes());
+        bytes[0x204..0x208].copy_from_slice(&0x0000_0401u32.to_le_bytes());
+
+        assert_eq!(
+            headers(&bytes),
+            vec![
+                Header::McuBoot {
+                    offset: 0,
+                    load_address: 0,
+                    header_size: 0x200,
+                    image_size: 0x100,
+                    version: "1.2.3+0".to_owned()
+                },
+                Header::VectorTable {
+                    offset: 0x200,
+                    initial_sp: 0x2002_0000,
+                    reset_handler: 0x401
+                }
+            ]
+        );
+        assert!(headers(&[0xFFu8; 64]).is_empty());
+    }
+
+    #[test]
+    fn uimage_crc() {
+        let data = b"kernel";
+        let mut bytes = vec![0u8; UIMAGE_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&UIMAGE_MAGIC.to_be_bytes());
+        bytes[12..16].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes[24..28].copy_from_slice(&crc32fast::hash(data).to_be_bytes());
+        bytes[32..37].copy_from_slice(b"linux");
+        let header_crc = crc32fast::hash(&bytes);
+        bytes[4..8].copy_from_slice(&header_crc.to_be_bytes());
+        bytes.extend_from_slice(data);
+
+        match uimage(&bytes) {
+            Some(Header::UImage {
+                name,
+                header_crc_ok,
+                data_crc_ok,
+                ..
+            }) => {
+                assert_eq!(name, "linux");
+                assert!(header_crc_ok);
+                assert!(data_crc_ok);
+            }
+            other => panic!("Unexpected header {:?}", other),
+        }
+    }
+}
@@ -1,102 +1,182 @@
-use crate::ops::Result;
+use crate::error::Result;
 use bytes::BytesMut;
-use failure::format_err;
 use ihex::reader::Reader;
 use ihex::record::*;
 use ihex::writer;
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::ops::Range;
+
+/// Largest address span from the lowest to the highest record, the holes
+/// in between are allocated as well.
+const MAX_SPAN: u64 = 256 << 20;
+
+/// Data records of an IntelHex file with their absolute addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct HexImage {
+    /// Address added to the offset of the following data records, set by
+    /// extended segment and extended linear address records.
+    upper: u64,
+    chunks: Vec<(u64, Vec<u8>)>,
+}
 
-pub fn convert_hex2bin(file_name: &Path) -> Result<BytesMut> {
-    let content = read_hex2string(file_name)?;
+impl HexImage {
+    /// Contiguous bytes from the lowest to the highest address, holes are
+    /// filled with zeros, the lowest address and the holes.
+    ///
+    /// Fails if the records span more than `MAX_SPAN` bytes.
+    fn into_bytes(self) -> Result<(BytesMut, u64, Vec<Range<usize>>)> {
+        let base = self.chunks.iter().map(|(address, _)| *address).min();
+        let base = base.unwrap_or(0);
+        let end = self
+            .chunks
+            .iter()
+            .map(|(address, value)| address + value.len() as u64)
+            .max()
+            .unwrap_or(0);
+        if end - base > MAX_SPAN {
+            return Err(err!(
+                OutOfBounds,
+                "IntelHex records span {:#x}..{:#x}, more than the {} MiB supported",
+                base,
+                end,
+                MAX_SPAN >> 20
+            ));
+        }
 
-    let mut ihex_reader = Reader::new_stopping_after_error_and_eof(content.as_str(), false, true);
+        let holes = self
+            .segments()
+            .windows(2)
+            .map(|pair| (pair[0].end - base) as usize..(pair[1].start - base) as usize)
+            .collect();
+
+        let mut bin = BytesMut::new();
+        bin.resize((end - base) as usize, 0x00);
+        for (address, value) in self.chunks {
+            let start = (address - base) as usize;
+            bin[start..start + value.len()].copy_from_slice(&value);
+        }
+        Ok((bin, base, holes))
+    }
 
-    // use iterator
-    ihex_reader.try_fold(BytesMut::new(), |bin, record| hex_record2bin(record?, bin))
+    /// Address ranges covered by data records, adjacent and overlapping
+    /// records are merged.
+    fn segments(&self) -> Vec<Range<u64>> {
+        let mut chunks: Vec<Range<u64>> = self
+            .chunks
+            .iter()
+            .map(|(address, value)| *address..address + value.len() as u64)
+            .collect();
+        chunks.sort_by_key(|chunk| chunk.start);
+
+        let mut segments: Vec<Range<u64>> = Vec::new();
+        for chunk in chunks {
+            match segments.last_mut() {
+                Some(last) if chunk.start <= last.end => {
+                    last.end = std::cmp::max(last.end, chunk.end)
+                }
+                _ => segments.push(chunk),
+            }
+        }
+        segments
+    }
 }
 
-fn hex_record2bin(record: Record, binary: BytesMut) -> Result<BytesMut> {
-    let bin = match record {
-        Record::Data { value, offset } => {
-            let mut bin = BytesMut::new();
-            bin.resize(offset as usize, 0x00);
-            bin.extend_from_slice(&value[..]);
-            bin
-        }
-        Record::EndOfFile => binary,
-        _ => {
-            return Err(format_err!("Unknown Record Type {:?}", record));
-        }
-    };
+fn read_image(content: &str) -> Result<HexImage> {
+    let mut ihex_reader = Reader::new_stopping_after_error_and_eof(content, false, true);
 
-    Ok(bin)
+    // use iterator
+    ihex_reader.try_fold(HexImage::default(), |image, record| {
+        hex_record2bin(
+            record.map_err(|e| err!(Format, "Malformed IntelHex record: {}", e))?,
+            image,
+        )
+    })
 }
 
-fn read_hex2string(name: &Path) -> Result<String> {
-    let mut file = OpenOptions::new()
-        .read(true)
-        .open(name)
-        .map_err(|err| format_err!("Failed to open {:?}: {}", name, err))?;
+/// Content of IntelHex records, the address of its first byte and the
+/// ranges of the content not covered by any record.
+pub fn convert_hex2bin(content: &str) -> Result<(BytesMut, u64, Vec<Range<usize>>)> {
+    read_image(content)?.into_bytes()
+}
 
-    let mut buf = String::new();
-    file.read_to_string(&mut buf)?;
+/// A single IntelHex record with a valid checksum.
+pub fn is_record(line: &str) -> bool {
+    Record::from_record_string(line).is_ok()
+}
+
+fn hex_record2bin(record: Record, mut image: HexImage) -> Result<HexImage> {
+    match record {
+        Record::Data { value, offset } => {
+            image.chunks.push((image.upper + u64::from(offset), value));
+        }
+        Record::ExtendedSegmentAddress(segment) => image.upper = u64::from(segment) << 4,
+        Record::ExtendedLinearAddress(upper) => image.upper = u64::from(upper) << 16,
+        // entry points are irrelevant for the content
+        Record::StartSegmentAddress { .. } | Record::StartLinearAddress(_) => {}
+        Record::EndOfFile => {}
+    }
 
-    Ok(buf)
+    Ok(image)
 }
 
-pub fn write_bin_as_hex_to_file(path: &Path, mut bytes: BytesMut) -> Result<()> {
-    let byte_count = 16;
-    let rec_count: f32 = bytes.len() as f32 / byte_count as f32;
-    let mut records: Vec<Record> = Vec::new();
+/// Bytes per data record.
+const RECORD_SIZE: u64 = 16;
+
+/// IntelHex records of `bytes`, starting at address `base`, without the
+/// ascending ranges `holes`.
+///
+/// Extended linear address records precede the data whenever the upper 16
+/// bits of the address change, data records never cross a 64 KiB boundary.
+pub fn convert_bin2hex(bytes: &[u8], base: u64, holes: &[Range<usize>]) -> Result<String> {
+    base.checked_add(bytes.len() as u64)
+        .filter(|end| *end <= 1 << 32)
+        .ok_or_else(|| {
+            err!(
+                OutOfBounds,
+                "Image of {} bytes at {:#x} exceeds the 32 bit IntelHex address space",
+                bytes.len(),
+                base
+            )
+        })?;
 
-    for ind in 0..rec_count.ceil() as usize {
-        if bytes.len() > byte_count {
-            // according to doc: split_to() is exclusive on the right: +1
-            // but tests state the opposite...
-            let data = bytes.split_to(byte_count);
-            records.push(Record::Data {
-                offset: (byte_count * ind) as u16,
-                value: data.to_vec(),
-            });
-        } else {
+    let mut records: Vec<Record> = Vec::new();
+    let mut upper = 0;
+    let mut pos = 0;
+    let len = bytes.len();
+    for hole in holes.iter().chain(std::iter::once(&(len..len))) {
+        // data between the previous hole and this one
+        let mut address = base + pos as u64;
+        let data_end = base + hole.start as u64;
+        while address < data_end {
+            if address >> 16 != upper {
+                upper = address >> 16;
+                records.push(Record::ExtendedLinearAddress(upper as u16));
+            }
+            let offset = address & 0xFFFF;
+            let len = RECORD_SIZE.min(data_end - address).min(0x1_0000 - offset);
+            let start = (address - base) as usize;
             records.push(Record::Data {
-                offset: 16 * ind as u16,
-                value: bytes.to_vec(),
+                offset: offset as u16,
+                value: bytes[start..start + len as usize].to_vec(),
             });
+            address += len;
         }
+        pos = hole.end;
     }
 
     let eof_rec = Record::EndOfFile;
     records.push(eof_rec);
 
-    let ihex_obj = writer::create_object_file_representation(&records)?;
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(path)
-        .map_err(|err| format_err!("Failed to open {:?}: {}", path, err))?;
-
-    file.write_all(ihex_obj.as_bytes())?;
+    let ihex_obj = writer::create_object_file_representation(&records)
+        .map_err(|e| err!(Format, "Failed to write IntelHex records: {}", e))?;
 
-    Ok(())
+    Ok(ihex_obj)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::error::Error;
     use bytes::BufMut;
-    use std::path::PathBuf;
-
-    #[test]
-    fn test_read_string_err() {
-        let file = PathBuf::from("NonExisitingFileName");
-        let res = read_hex2string(file.as_ref());
-        assert!(res.is_err());
-    }
 
     #[test]
     fn test_hex_convert() {
@@ -111,36 +191,81 @@ mod test {
 
         let mut reader = Reader::new_stopping_after_error_and_eof(&file, false, true);
 
-        let res = reader.try_fold(BytesMut::new(), |bin, record| hex_record2bin(record?, bin));
+        let res = reader.try_fold(HexImage::default(), |image, record| {
+            hex_record2bin(
+                record.map_err(|e| err!(Format, "Malformed IntelHex record: {}", e))?,
+                image,
+            )
+        });
 
         println!("{:?}", res);
 
-        assert!(res.is_ok());
+        let (bin, base, holes) = res
+            .expect("Failed to convert")
+            .into_bytes()
+            .expect("Failed to collect");
+        assert_eq!(base, 0);
+        assert!(holes.is_empty());
+        assert_eq!(bin.len(), 0x60);
+        assert_eq!(bin[0x00], 1);
+        assert_eq!(bin[0x58], 0xFE);
     }
 
     #[test]
     fn test_eof_record() {
         let record = Record::EndOfFile;
-        let buf_vec = [0, 0];
-        let buf = BytesMut::from(&buf_vec[..]);
-        let res = hex_record2bin(record, buf.clone());
+        let image = HexImage {
+            upper: 0,
+            chunks: vec![(0, vec![0, 0])],
+        };
+        let res = hex_record2bin(record, image.clone());
 
-        assert_eq!(buf, res.unwrap());
+        assert_eq!(image, res.unwrap());
     }
 
     #[test]
-    fn test_bad_record() {
-        let buf_vec = [0, 0];
-        let buf = BytesMut::from(&buf_vec[..]);
-        let record = Record::ExtendedLinearAddress(8);
-        let res = hex_record2bin(record, buf);
-
-        assert!(res.is_err());
+    fn test_extended_address_records() {
+        let records = vec![
+            Record::ExtendedLinearAddress(0x0800),
+            Record::Data {
+                offset: 0x0010,
+                value: vec![0xAA, 0xBB],
+            },
+            Record::StartLinearAddress(0x0800_0101),
+            Record::Data {
+                offset: 0x0000,
+                value: vec![0x11],
+            },
+            Record::Data {
+                offset: 0x0012,
+                value: vec![0xCC],
+            },
+            Record::EndOfFile,
+        ];
+        let image = records
+            .into_iter()
+            .try_fold(HexImage::default(), |image, record| {
+                hex_record2bin(record, image)
+            })
+            .expect("Failed to convert");
+
+        assert_eq!(
+            image.segments(),
+            vec![0x0800_0000..0x0800_0001, 0x0800_0010..0x0800_0013]
+        );
+        let (bin, base, holes) = image.into_bytes().expect("Failed to collect");
+        assert_eq!(base, 0x0800_0000);
+        assert_eq!(holes, vec![1..0x10]);
+        assert_eq!(bin.len(), 0x13);
+        assert_eq!(&bin[..2], &[0x11, 0x00]);
+        assert_eq!(&bin[0x10..], &[0xAA, 0xBB, 0xCC]);
+
+        let image = hex_record2bin(Record::ExtendedSegmentAddress(0x1000), HexImage::default());
+        assert_eq!(image.unwrap().upper, 0x1_0000);
     }
 
     #[test]
     fn test_write_hex() {
-        let name = PathBuf::from("tmp_write.hex");
         let hex = ":1000000001000000000000000200000000000000ED
 :1000100003000000000000000400000000000000D9
 :1000200005000000000000000600000000000000C5
@@ -164,25 +289,57 @@ mod test {
         bytes.put_u64_le(11);
         bytes.put_u64_le(254);
 
-        write_bin_as_hex_to_file(name.as_ref(), bytes).expect("Failed to write bytes to hex file");
-
-        let mut hex_file = OpenOptions::new()
-            .read(true)
-            .open(&name)
-            .map_err(|err| format_err!("{}", err))
-            .expect("Failed to open stitched file");
-
-        let mut content = String::new();
-        hex_file
-            .read_to_string(&mut content)
-            .expect("Failed to read hex file");
+        let content = convert_bin2hex(&bytes, 0, &[]).expect("Failed to convert bytes to hex");
         println!("{}", content);
 
         // add a more sophisticated test
         assert_eq!(content, hex);
+    }
+
+    #[test]
+    fn write_extended_address() {
+        let bytes = BytesMut::from(&[0xAA, 0xBB][..]);
+        let content = convert_bin2hex(&bytes, 0x0800_0000, &[]).expect("Failed to convert");
+        assert_eq!(content, ":020000040800F2\n:02000000AABB99\n:00000001FF");
+        assert_eq!(
+            convert_hex2bin(&content).expect("Failed to read"),
+            (bytes, 0x0800_0000, vec![])
+        );
+
+        // crossing 64 KiB boundaries from an unaligned base
+        let bytes: BytesMut = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let content = convert_bin2hex(&bytes, 0xFFF8, &[]).expect("Failed to convert");
+        assert!(content.starts_with(":08FFF80000"));
+        assert!(content.contains("\n:020000040001F9\n:10000000"));
+        let (read, base, _) = convert_hex2bin(&content).expect("Failed to read");
+        assert_eq!(base, 0xFFF8);
+        assert_eq!(read, bytes);
+
+        assert!(convert_bin2hex(&[0u8; 2], 0xFFFF_FFFF, &[]).is_err());
+    }
 
-        // delete file
-        std::fs::remove_file(name).expect("failed to delete tmp file");
+    #[test]
+    fn sparse_segments() {
+        // 4 bytes at 0x0800_0000 and 0x0810_0000 each
+        let content = ":020000040800F2\n:0400000001020304F2\n:020000040810E2\n:04000000AABBCCDDEE\n:00000001FF";
+        let (bytes, base, holes) = convert_hex2bin(content).expect("Failed to read");
+        assert_eq!(base, 0x0800_0000);
+        assert_eq!(bytes.len(), 0x10_0004);
+        assert_eq!(holes, vec![4..0x10_0000]);
+
+        // no records are written for the hole
+        let written = convert_bin2hex(&bytes, base, &holes).expect("Failed to convert");
+        assert_eq!(written, content);
+    }
+
+    #[test]
+    fn span_too_large() {
+        // flash at 0x0800_0000 and option bytes at 0x2000_0000
+        let content = ":020000040800F2\n:0400000001020304F2\n:020000042000DA\n:04000000AABBCCDDEE\n:00000001FF";
+        match convert_hex2bin(content) {
+            Err(Error::OutOfBounds(_)) => {}
+            other => panic!("Unexpected {:?}", other),
+        }
     }
 
     #[test]
@@ -193,7 +350,12 @@ mod test {
             .to_string();
 
         let mut reader = Reader::new_stopping_after_error_and_eof(&bad_hex, false, true);
-        let res = reader.try_fold(BytesMut::new(), |bin, record| hex_record2bin(record?, bin));
+        let res = reader.try_fold(HexImage::default(), |image, record| {
+            hex_record2bin(
+                record.map_err(|e| err!(Format, "Malformed IntelHex record: {}", e))?,
+                image,
+            )
+        });
 
         assert!(res.is_err());
     }
@@ -0,0 +1,131 @@
+use goblin::elf::{sym, Elf};
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::range::Range;
+
+/// A named region of an image.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Region {
+    pub name: String,
+    pub range: Range,
+}
+
+/// Named regions of an image, either from a layout manifest or the
+/// symbols of an ELF file.
+///
+/// A layout manifest is a TOML file listing the regions:
+///
+/// ```toml
+/// [[region]]
+/// name = "bootloader"
+/// range = "0..16Ki"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Layout {
+    #[serde(rename = "region", default)]
+    pub regions: Vec<Region>,
+}
+
+impl Layout {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::Io(format!("Failed to open {:?}", path), e))?;
+        toml::from_str(&content)
+            .map_err(|e| err!(Format, "Failed to parse layout {:?}: {}", path, e))
+    }
+
+    /// Function and object symbols of an ELF file, `base` is the address
+    /// of the first byte of the image.
+    pub fn from_elf(path: &Path, base: u64) -> Result<Self> {
+        let content =
+            fs::read(path).map_err(|e| Error::Io(format!("Failed to open {:?}", path), e))?;
+        let elf = Elf::parse(&content)
+            .map_err(|e| err!(Format, "Failed to parse ELF {:?}: {}", path, e))?;
+
+        let mut regions = elf
+            .syms
+            .iter()
+            .filter(|symbol| {
+                symbol.st_size > 0
+                    && (symbol.st_type() == sym::STT_FUNC || symbol.st_type() == sym::STT_OBJECT)
+            })
+            .filter_map(|symbol| {
+                let name = elf.strtab.get(symbol.st_name)?.ok()?;
+                // thumb functions have the lowest address bit set
+                let start = if symbol.st_type() == sym::STT_FUNC {
+                    symbol.st_value & !1
+                } else {
+                    symbol.st_value
+                };
+                // symbols below the image, e.g. in RAM, are skipped
+                Some(Region {
+                    name: name.to_owned(),
+                    range: Range::from_u64(start.checked_sub(base)?, symbol.st_size),
+                })
+            })
+            .collect::<Vec<_>>();
+        regions.sort_by_key(|region| region.range.start.clone());
+        Ok(Self { regions })
+    }
+
+    /// Names of all regions overlapping `[start, end)`.
+    pub fn names_overlapping(&self, start: u64, end: u64) -> Vec<&str> {
+        self.regions
+            .iter()
+            .filter(|region| {
+                let region_start = region.range.start.as_u64().unwrap_or(u64::MAX);
+                let region_end = region.range.end().and_then(|end| end.as_u64());
+                let region_end = region_end.unwrap_or(u64::MAX);
+                region_start < end && start < region_end
+            })
+            .map(|region| region.name.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn manifest() {
+        let layout: Layout = toml::from_str(
+            r#"
+            [[region]]
+            name = "bootloader"
+            range = "0..16Ki"
+
+            [[region]]
+            name = "config"
+            range = "16Ki+4Ki"
+
+            [[region]]
+            name = "app"
+            range = "20Ki..1Mi"
+            "#,
+        )
+        .expect("Failed to parse layout");
+
+        assert_eq!(layout.regions.len(), 3);
+        assert_eq!(layout.names_overlapping(0, 1), vec!["bootloader"]);
+        assert_eq!(
+            layout.names_overlapping(16 * 1024 - 1, 16 * 1024 + 1),
+            vec!["bootloader", "config"]
+        );
+        assert!(layout
+            .names_overlapping(1024 * 1024, 1024 * 1024 + 1)
+            .is_empty());
+    }
+
+    #[test]
+    fn bad_elf() {
+        let path = std::env::temp_dir().join(format!("scalpel-bad-elf-{}", std::process::id()));
+        fs::write(&path, b"\x7fELF but not really").expect("Failed to write");
+        let layout = Layout::from_elf(&path, 0);
+        std::fs::remove_file(&path).expect("failed to delete tmp file");
+        assert!(layout.is_err());
+    }
+}
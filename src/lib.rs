@@ -0,0 +1,55 @@
+//! Cut, stitch, graft and patch firmware images.
+//!
+//! The `scalpel` command line tool is a thin wrapper around this library,
+//! build tooling can call the same operations directly:
+//!
+//! ```
+//! use scalpel::{AnnotatedBytes, ByteOffset, Error, FillPattern, GraftMode, MetaInfo, Range};
+//! use std::str::FromStr;
+//!
+//! let mut image = AnnotatedBytes::parse(&[0xFF; 64], MetaInfo::Bin)?;
+//! let signature = AnnotatedBytes::parse(&[0xA5; 8], MetaInfo::Bin)?;
+//! let range = Range::from_str("56..64")?;
+//! image.graft(signature, range.start, range.size, FillPattern::Zero, GraftMode::Strict)?;
+//! image.stance(ByteOffset::from_u64(48), ByteOffset::from_u64(16))?;
+//! assert_eq!(&image.encode(MetaInfo::Bin)?[8..], &[0xA5; 8][..]);
+//!
+//! // errors tell what went wrong
+//! let range = Range::from_str("56..72")?;
+//! let mut image = AnnotatedBytes::parse(&[0xFF; 64], MetaInfo::Bin)?;
+//! let signature = AnnotatedBytes::parse(&[0xA5; 16], MetaInfo::Bin)?;
+//! match image.graft(signature, range.start, range.size, FillPattern::Zero, GraftMode::Strict) {
+//!     Err(Error::OutOfBounds(_)) => {}
+//!     other => panic!("Unexpected {:?}", other),
+//! }
+//! # Ok::<(), Error>(())
+//! ```
+
+#[macro_use]
+pub mod error;
+
+pub mod byte_offset;
+pub mod delta;
+pub mod device;
+pub mod diff;
+pub mod dump;
+pub mod expression;
+pub mod info;
+pub mod intelhex;
+pub mod layout;
+pub mod ops;
+pub mod patch;
+pub mod range;
+pub mod search;
+pub mod sector;
+pub mod split;
+pub mod stream;
+pub mod target;
+pub mod transform;
+
+pub use crate::byte_offset::{ByteOffset, Magnitude};
+pub use crate::error::{Error, Result};
+pub use crate::ops::{AnnotatedBytes, FillPattern, GraftMode, MetaInfo};
+pub use crate::range::Range;
+pub use crate::stream::{StreamedBytes, StreamedFile};
+pub use crate::target::Target;
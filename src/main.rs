@@ -1,28 +1,48 @@
-use common_failures::quick_main;
 use docopt::Docopt;
-use failure::format_err;
+use log::info;
 use serde_derive::Deserialize;
-use std::path::PathBuf;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
 
-use failure::Error;
-
-mod byte_offset;
-mod intelhex;
-mod range;
-
-mod ops;
-use crate::ops::{AnnotatedBytes, FillPattern, MetaInfo, Result};
-
-use crate::byte_offset::*;
-use crate::range::*;
+use scalpel::byte_offset::*;
+use scalpel::device::DeviceProfile;
+use scalpel::dump::DumpFormat;
+use scalpel::error::{Error, Result};
+use scalpel::layout::Layout;
+use scalpel::ops::{
+    content_seed, AnnotatedBytes, Detection, Endianness, FillPattern, GraftMode, MetaInfo,
+};
+use scalpel::patch::PatchValue;
+use scalpel::range::*;
+use scalpel::search::Pattern;
+use scalpel::sector::SectorMap;
+use scalpel::stream::{StreamedBytes, StreamedFile};
+use scalpel::target::Target;
+use scalpel::transform::Transform;
+use scalpel::{delta, diff, dump, info, ops, split, stream};
 
 const USAGE: &str = "
 scalpel
 
 Usage:
-  scalpel stance --range=<range> --output=<output> <input> [--file-format=<format>]
-  scalpel stitch (--input=<input> --offset=<offset>)... [--fill-pattern=<fill_pattern>] [--file-format=<format>] --output=<output>
-  scalpel graft --replace=<replace> --range=<range>  [--fill-pattern=<fill_pattern>] [--file-format=<format>] --output=<output> <input>
+  scalpel stance --range=<range> --output=<output> <input> [--file-format=<format>] [--input-format=<format>...] [--align=<align>] [--align-pad] [--device=<device>] [--fill-pattern=<fill_pattern>] [--seed=<seed> | --reproducible] [--swap=<swap> [--swap-pad]]
+  scalpel stitch (--input=<input> --offset=<offset>)... [--fill-pattern=<fill_pattern>] [--file-format=<format>] [--align=<align>] [--align-pad] [--device=<device>] [--seed=<seed> | --reproducible] [--swap=<swap> [--swap-pad]] [--input-format=<format>...] --output=<output>
+  scalpel graft --replace=<replace> --range=<range>  [--graft-mode=<graft_mode>] [--fill-pattern=<fill_pattern>] [--file-format=<format>] [--align=<align>] [--align-pad] [--device=<device>] [--seed=<seed> | --reproducible] [--swap=<swap> [--swap-pad]] [--input-format=<format>...] (--output=<output> | --in-place [--backup-suffix=<suffix>]) <input>
+  scalpel patch (--at=<offset> --value=<value>)... [--file-format=<format>] [--input-format=<format>...] (--output=<output> | --in-place [--backup-suffix=<suffix>]) <input>
+  scalpel insert --at=<offset> (--value=<value> | --from=<from>) [--keep-size] [--fill-pattern=<fill_pattern>] [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
+  scalpel delete --range=<range> [--keep-size] [--fill-pattern=<fill_pattern>] [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
+  scalpel diff [--gap=<gap>] [--layout=<layout> | --elf=<elf> [--base=<base>]] [--hexdump] [--input-format=<format>...] <input> <other>
+  scalpel delta create [--input-format=<format>...] --output=<output> <input> <other>
+  scalpel delta apply --delta=<delta> [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
+  scalpel dump [--range=<range>] [--width=<width>] [--group=<group>] [--endianness=<endianness>] [--base=<base> | --device=<device>] [--no-collapse] [--input-format=<format>...] <input>
+  scalpel info [--block-size=<block_size>] [--json] [--input-format=<format>...] <input>
+  scalpel find --pattern=<pattern> [--alignment=<alignment>] [--base=<base> | --device=<device>] [--input-format=<format>...] <input>
+  scalpel replace --pattern=<pattern> --with=<value> [--count=<count>] [--resize] [--alignment=<alignment>] [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
+  scalpel split --chunk-size=<chunk_size> [--pad-last] [--fill-pattern=<fill_pattern>] [--manifest=<manifest>] [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
+  scalpel deinterleave --ways=<ways> [--width=<width>] [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
+  scalpel interleave (--input=<input>)... [--width=<width>] [--file-format=<format>] [--input-format=<format>...] --output=<output>
+  scalpel swap --width=<width> [--range=<range>] [--swap-pad] [--fill-pattern=<fill_pattern>] [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
+  scalpel transform (--op=<op>)... [--range=<range>] [--file-format=<format>] [--input-format=<format>...] --output=<output> <input>
   scalpel (-h | --help)
   scalpel (-v |--version)
 
@@ -30,15 +50,100 @@ Commands:
   stance  extract bytes from a binary file
   stitch  stitchs binaries together, each file starts at <offset> with (random|one|zero) padding, accepted file formats: binary, IntelHex
   graft   replace a section with <replace> specfied by start and end/size
+  patch   write literal values at offsets, the size of the file is unchanged
+  insert  splice a value or the content of a file in at an offset, growing the file
+  delete  cut a range out, shrinking the file
+  diff    list the differing ranges of two files, exits with 1 if they differ
+  delta   create a delta turning <input> into <other>, or apply a delta to <input>,
+          a delta only applies to the exact image it was created from
+  info    summarize format, size, address segments and holes, erased regions, entropy, known headers
+          (uImage, MCUboot, ARM vector table, ELF) and checksums
+  find    print the offset of every match of a pattern, followed by its address if the base address is known,
+          exits with 1 if there is no match
+  replace replace every non-overlapping match of a pattern, prints the offset of each replaced match
+  split   cut the file into chunks of <chunk_size> bytes, written to <output> with {index} replaced by the chunk index,
+          prints offset, size and CRC-32 of each chunk
+  deinterleave
+          distribute the words of <width> bytes round robin onto <ways> lanes, i.e. even and odd bytes
+          of a 16 bit bus, written to <output> with {index} replaced by the lane index,
+          each lane of an IntelHex input starts at its address divided by <ways>
+  interleave
+          merge lanes taking a word of <width> bytes from each input in turn, IntelHex lanes
+          must share their address, the result starts at it multiplied by the number of lanes
+  swap    reverse the byte order within each word of <width> bytes of the whole file or of a range
+  transform
+          apply bitwise transformations in the given order to the whole file or to a range
+  dump    print a hexdump of the whole file or of a range, repeated lines are collapsed into a single *
 
 Options:
   -h --help                     Show this screen.
   -v --version                  Show version.
+  -o --output=<output>          output file
   --range=<range>               byte range in rust slice-like sytnax: <start>..<end> yields [start,end) or <start>+<size> yields [start, start+size]
-                                accepts the units K, Ki, M, Mi, G, Gi. Examples: 12K..4Ki   12M+512
-  --fill-pattern=<fill_patern>  Specify padding style for stitching files (random|one|zero)
+                                accepts the units K, Ki, M, Mi, G, Gi, T, Ti, P, Pi, E, Ei. Examples: 12K..4Ki   12M+512
+                                start, end and size may be expressions using + - * / ( ) align_up(x, a) and align_down(x, a),
+                                arithmetic in <start> of <start>+<size> must be parenthesized. Example: (16Ki*3)+4Ki
+  --offset=<offset>             byte offset, same syntax as a range boundary. Example: 0x08000000+64Ki-0x200
+  --fill-pattern=<fill_patern>  Specify padding style (random|one|zero|byte:<value>|pattern:<hex>|file:<path>|address[:<word>])
+                                pattern and file repeat their bytes, address fills each word with its own offset,
+                                <word> is one of u8, u16le, u16be, u32le (default), u32be, u64le, u64be.
+                                Examples: byte:0xA5   pattern:DEADBEEF   address:u32be   random:0x5eed
+  --seed=<seed>                 seed for a random fill pattern, decimal or hex, makes the padding reproducible
+  --reproducible                seed a random fill pattern from the content of all inputs, identical inputs yield
+                                identical outputs
   --replace=<replace>           File which replaces the original part
+  --graft-mode=<graft_mode>     if the replacement is larger than the range either fail (strict, default), grow the file
+                                shifting the remainder (grow) or truncate the replacement (truncate). A range beyond the
+                                end of the file is padded with the fill pattern by grow and cut at the end of the file by truncate.
   --file-format=<format>        define output file format as either bin (default) or hex, has no influence on file ending!
+  --input-format=<format>       format of the inputs as either bin or hex, given once for all inputs or once per input in the
+                                order of the inputs, i.e. <input> before <other> or --replace and each --input in turn.
+                                Detected from the file ending or the content by default, required when reading from stdin
+  --align=<align>               verify that ranges and offsets are aligned to sector boundaries, either a uniform sector size
+                                or a sector map of <size>[:<count>] entries starting at 0, the last size repeats.
+                                Defaults to the sector map of --device. Examples: 4Ki   16Ki:4,64Ki,128Ki:7
+  --align-pad                   pad the output with the fill pattern up to the next sector boundary, requires --align or --device
+  --at=<offset>                 offset of the following --value, same syntax as --offset
+  --value=<value>               value to patch as <type>:<value>, types are hex, file, str, strz, str/<len>, strz/<len> (NUL padded),
+                                u8, i8, u16le, u16be, i16le, i16be, u32le, u32be, i32le, i32be, u64le, u64be, i64le, i64be,
+                                f32le, f32be, f64le, f64be. Examples: hex:DEADBEEF   strz/16:v1.2.3   u32be:42   f32le:1.5
+  --from=<from>                 file whose content is inserted
+  --keep-size                   keep the file size constant, truncate after insert or pad with the fill pattern after delete
+  --in-place                    modify <input> instead of writing an output, a binary file is only written where it changes,
+                                an IntelHex file or a binary whose tail shifts is replaced atomically
+  --backup-suffix=<suffix>      copy <input> to its name with <suffix> appended before modifying it in place. Example: .orig
+  --gap=<gap>                   coalesce differing ranges separated by at most <gap> equal bytes [default: 0]
+  --layout=<layout>             TOML layout manifest of named regions to annotate the differences with, see README
+  --elf=<elf>                   ELF file whose function and object symbols annotate the differences
+  --base=<base>                 address of the first byte of the file, diff subtracts it from symbol addresses,
+                                dump defaults to the address of an IntelHex input or the flash base of the device
+  --hexdump                     print a side by side hexdump of each difference
+  --delta=<delta>               delta file created by delta create
+  --width=<width>               bytes per hexdump line (default 16), bytes per word of interleave and deinterleave (default 1)
+                                and of swap (2, 4 or 8)
+  --ways=<ways>                 number of lanes to deinterleave into
+  --swap=<swap>                 reverse the byte order within each word of <swap> bytes of the output, either 2, 4 or 8
+  --op=<op>                     transformation, one of xor:<value> (XOR with the repeated key <value>, same syntax as --value,
+                                starting at the first byte of the range), invert, bit-reverse (within each byte) or swap:<width>.
+                                Examples: xor:hex:A55A   xor:file:key.bin   invert   bit-reverse   swap:4
+  --swap-pad                    pad the end of the file with the fill pattern to a multiple of the swap width instead of failing
+  --group=<group>               bytes printed as one word, divides the width [default: 1]
+  --endianness=<endianness>     byte order of the words, big prints the bytes in memory order (big|little) [default: big]
+  --no-collapse                 print repeated hexdump lines
+  --block-size=<block_size>     size of the blocks the entropy is calculated for [default: 4Ki]
+  --json                        print the summary as JSON
+  --pattern=<pattern>           pattern to search as <type>:<pattern>, types are hex (?? matches any byte), str and regex.
+                                Examples: hex:DEADBEEF   hex:7F454C46??01   str:v1.2.3   regex:v[0-9]+\\.[0-9]+
+  --alignment=<alignment>       only report matches starting at a multiple of <alignment> [default: 1]
+  --with=<value>                replacement of each match, same syntax as --value. Examples: file:key.der   hex:00000000
+  --count=<count>               fail unless exactly <count> matches are found
+  --chunk-size=<chunk_size>     size of each chunk, the last one may be shorter. Example: 4Ki
+  --pad-last                    pad the last chunk with the fill pattern to the full chunk size
+  --manifest=<manifest>         write a TOML manifest of all chunk files, offsets, sizes, CRC-32 and SHA-256
+  --resize                      allow a replacement of a different length than the match, shifting the remainder
+  --device=<device>             flash device profile, either the name of a built-in profile or a profile file, see README.
+                                Provides the default fill pattern and sector map, validates the output size against the
+                                flash size and offsets against the write granularity. graft reports the sectors touched.
 ";
 
 #[derive(Debug, Deserialize)]
@@ -46,6 +151,23 @@ struct Args {
     cmd_stance: bool,
     cmd_stitch: bool,
     cmd_graft: bool,
+    cmd_patch: bool,
+    cmd_insert: bool,
+    cmd_delete: bool,
+    cmd_diff: bool,
+    cmd_delta: bool,
+    cmd_create: bool,
+    cmd_apply: bool,
+    cmd_dump: bool,
+    cmd_info: bool,
+    cmd_find: bool,
+    cmd_replace: bool,
+    cmd_split: bool,
+    cmd_deinterleave: bool,
+    cmd_interleave: bool,
+    cmd_swap: bool,
+    cmd_transform: bool,
+    arg_other: PathBuf,
     arg_input: PathBuf,
     flag_input: Vec<PathBuf>,
     flag_offset: Vec<ByteOffset>,
@@ -53,7 +175,44 @@ struct Args {
     flag_output: PathBuf,
     flag_fill_pattern: Option<FillPattern>,
     flag_file_format: Option<MetaInfo>,
+    flag_input_format: Vec<MetaInfo>,
     flag_replace: PathBuf,
+    flag_graft_mode: Option<GraftMode>,
+    flag_align: Option<SectorMap>,
+    flag_align_pad: bool,
+    flag_device: Option<String>,
+    flag_seed: Option<ByteOffset>,
+    flag_at: Vec<ByteOffset>,
+    flag_value: Vec<PatchValue>,
+    flag_from: Option<PathBuf>,
+    flag_keep_size: bool,
+    flag_in_place: bool,
+    flag_backup_suffix: Option<String>,
+    flag_gap: ByteOffset,
+    flag_layout: Option<PathBuf>,
+    flag_elf: Option<PathBuf>,
+    flag_base: Option<ByteOffset>,
+    flag_width: Option<usize>,
+    flag_ways: Option<usize>,
+    flag_swap: Option<usize>,
+    flag_swap_pad: bool,
+    flag_op: Vec<Transform>,
+    flag_group: usize,
+    flag_endianness: Endianness,
+    flag_no_collapse: bool,
+    flag_block_size: ByteOffset,
+    flag_json: bool,
+    flag_pattern: Option<Pattern>,
+    flag_alignment: ByteOffset,
+    flag_with: Option<PatchValue>,
+    flag_count: Option<usize>,
+    flag_resize: bool,
+    flag_chunk_size: Option<ByteOffset>,
+    flag_pad_last: bool,
+    flag_manifest: Option<PathBuf>,
+    flag_hexdump: bool,
+    flag_delta: PathBuf,
+    flag_reproducible: bool,
     flag_version: bool,
     flag_help: bool,
 }
@@ -61,6 +220,268 @@ struct Args {
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const NAME: &str = env!("CARGO_PKG_NAME");
 
+/// Inputs of the command in the order `--input-format` refers to them.
+fn inputs(args: &Args) -> Vec<&PathBuf> {
+    if args.cmd_stitch || args.cmd_interleave {
+        args.flag_input.iter().collect()
+    } else if args.cmd_graft {
+        vec![&args.arg_input, &args.flag_replace]
+    } else if args.cmd_diff || (args.cmd_delta && args.cmd_create) {
+        vec![&args.arg_input, &args.arg_other]
+    } else {
+        std::iter::once(&args.arg_input)
+            .chain(args.flag_from.iter())
+            .collect()
+    }
+}
+
+/// Format of input `idx` starting with `header`, either given by
+/// `--input-format` or detected from the extension or the content.
+/// The format of stdin must be given.
+fn input_format(args: &Args, idx: usize, header: &[u8]) -> Result<(MetaInfo, Detection)> {
+    let inputs = inputs(args);
+    let path = inputs[idx];
+    let (meta_in, detection) = match args.flag_input_format.len() {
+        0 if ops::is_stdio(path) => {
+            return Err(Error::Parse(
+                "Reading stdin requires --input-format".to_owned(),
+            ));
+        }
+        0 => MetaInfo::detect(path, header)
+            .map_err(|e| Error::Format(format!("{:?}: {}", path, e)))?,
+        1 => (args.flag_input_format[0], Detection::Explicit),
+        n if n == inputs.len() => (args.flag_input_format[idx], Detection::Explicit),
+        n => {
+            return Err(Error::Parse(format!(
+                "{} input formats given for {} inputs, expected one for all or one per input",
+                n,
+                inputs.len()
+            )));
+        }
+    };
+    info!("{:?} is {:?}, detected by {}", path, meta_in, detection);
+    Ok((meta_in, detection))
+}
+
+/// Load input `idx`, `-` reads stdin.
+fn load_input(args: &Args, idx: usize) -> Result<(AnnotatedBytes, MetaInfo)> {
+    let content = ops::read_input(inputs(args)[idx])?;
+    let (meta_in, _detection) = input_format(args, idx, &content)?;
+    let bytes = AnnotatedBytes::parse(&content, meta_in)?;
+    Ok((bytes, meta_in))
+}
+
+/// stdin can only be read once.
+fn check_stdin(args: &Args) -> Result<()> {
+    let stdin_inputs = inputs(args)
+        .into_iter()
+        .chain(std::iter::once(&args.flag_delta))
+        .filter(|path| ops::is_stdio(path))
+        .count();
+    if stdin_inputs > 1 {
+        Err(Error::Parse(
+            "Only one input can be read from stdin".to_owned(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Print a report line to stdout, or to stderr if the output goes to stdout.
+macro_rules! report {
+    ($args:expr, $($arg:tt)*) => {
+        if ops::is_stdio(&$args.flag_output) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+fn device(args: &Args) -> Result<Option<DeviceProfile>> {
+    args.flag_device
+        .as_ref()
+        .map(|device| DeviceProfile::lookup(device))
+        .transpose()
+}
+
+/// Explicitly given fill pattern or the one of the device.
+///
+/// A random fill pattern is seeded by `--seed` or, with `--reproducible`,
+/// from the content of all `inputs`.
+fn fill_pattern(
+    args: &Args,
+    device: Option<&DeviceProfile>,
+    inputs: &[&AnnotatedBytes],
+) -> Result<FillPattern> {
+    fill_pattern_seeded(args, device, || {
+        Ok(content_seed(inputs.iter().map(|input| &input.bytes[..])))
+    })
+}
+
+/// `fill_pattern` with the content seed calculated by `seed`, which is only
+/// called if the seed is needed.
+fn fill_pattern_seeded<F: FnOnce() -> Result<u64>>(
+    args: &Args,
+    device: Option<&DeviceProfile>,
+    seed: F,
+) -> Result<FillPattern> {
+    let fill_pattern = match (&args.flag_fill_pattern, device) {
+        (Some(fill_pattern), _) => fill_pattern.clone(),
+        (None, Some(device)) => device.fill_pattern(),
+        (None, None) => FillPattern::default(),
+    };
+    match (fill_pattern, &args.flag_seed, args.flag_reproducible) {
+        (FillPattern::Random, Some(seed), _) => Ok(FillPattern::SeededRandom(seed.as_u64()?)),
+        (FillPattern::Random, None, true) => Ok(FillPattern::SeededRandom(seed()?)),
+        (fill_pattern, _, _) => Ok(fill_pattern),
+    }
+}
+
+/// Reverse the byte order within each word of `width` bytes of `range` or
+/// of the whole file, padded with `--swap-pad`.
+fn swap(
+    bytes: &mut AnnotatedBytes,
+    args: &Args,
+    range: Option<&Range>,
+    width: usize,
+    fill_pattern: &FillPattern,
+) -> Result<()> {
+    let range = match range {
+        Some(range) => range.clone(),
+        None => Range::from_u64(0, bytes.bytes.len() as u64),
+    };
+    let pad = Some(fill_pattern).filter(|_| args.flag_swap_pad);
+    bytes.swap_padded(&range.start, &range.size, width, pad)
+}
+
+/// Pad to the next sector boundary if requested, swap the byte order if
+/// requested and check the result fits onto the device.
+fn prepare_output(
+    bytes: &mut AnnotatedBytes,
+    args: &Args,
+    device: Option<&DeviceProfile>,
+    fill_pattern: &FillPattern,
+) -> Result<()> {
+    if let Some(sectors) = align_pad_sectors(args, device)? {
+        bytes.pad_to_sector(sectors, fill_pattern)?;
+    }
+    if let Some(width) = args.flag_swap {
+        swap(bytes, args, None, width, fill_pattern)?;
+    }
+    if let Some(device) = device {
+        device.check_size(bytes.bytes.len())?;
+    }
+    Ok(())
+}
+
+/// Sector map of `--align`, or the one of the device.
+fn sectors<'a>(args: &'a Args, device: Option<&'a DeviceProfile>) -> Option<&'a SectorMap> {
+    args.flag_align
+        .as_ref()
+        .or_else(|| device.map(|device| &device.sectors))
+}
+
+/// Sectors to pad the output to if `--align-pad` is given.
+fn align_pad_sectors<'a>(
+    args: &'a Args,
+    device: Option<&'a DeviceProfile>,
+) -> Result<Option<&'a SectorMap>> {
+    if !args.flag_align_pad {
+        return Ok(None);
+    }
+    sectors(args, device)
+        .map(Some)
+        .ok_or_else(|| Error::Parse("--align-pad requires --align or --device".to_owned()))
+}
+
+/// Where `--output` or `--in-place` saves the edited input.
+fn target(args: &Args) -> Target {
+    if args.flag_in_place {
+        Target::InPlace {
+            input: args.arg_input.clone(),
+            backup_suffix: args.flag_backup_suffix.clone(),
+        }
+    } else {
+        Target::Output(args.flag_output.clone())
+    }
+}
+
+/// Binary inputs are streamed instead of loaded into memory, unless the
+/// output is IntelHex, swapped or overwrites one of the inputs.
+fn streamable(args: &Args) -> Result<bool> {
+    if args.flag_swap.is_some() {
+        return Ok(false);
+    }
+    let inputs: Vec<&Path> = inputs(args).into_iter().map(PathBuf::as_path).collect();
+    let meta_out = args.flag_file_format.unwrap_or_default();
+    target(args).streamable(&inputs, meta_out, |idx, header| {
+        Ok(input_format(args, idx, header)?.0)
+    })
+}
+
+/// Open `path` for streaming, together with its size.
+fn open_streamed(path: &Path) -> Result<(StreamedFile, u64)> {
+    let file = StreamedFile::open(path)?;
+    let len = file.size()?;
+    Ok((file, len))
+}
+
+/// `prepare_output` of a streamed image, which is never swapped.
+fn prepare_streamed_output(
+    bytes: &mut StreamedBytes,
+    args: &Args,
+    device: Option<&DeviceProfile>,
+) -> Result<()> {
+    if let Some(sectors) = align_pad_sectors(args, device)? {
+        bytes.pad_to_sector(sectors)?;
+    }
+    if let Some(device) = device {
+        device.check_size(usize::try_from(bytes.len())?)?;
+    }
+    Ok(())
+}
+
+/// Report a truncated replacement and the sectors of the device touched by
+/// a graft of `replacement_len` bytes into `range` of an image of `len` bytes.
+fn report_graft(
+    args: &Args,
+    device: Option<&DeviceProfile>,
+    range: &Range,
+    mode: GraftMode,
+    len: u64,
+    replacement_len: u64,
+) -> Result<()> {
+    let written = mode.written(
+        len,
+        range.start.as_u64()?,
+        range.size.as_u64()?,
+        replacement_len,
+    )?;
+    let size = written.end - written.start;
+    if replacement_len > size {
+        report!(
+            args,
+            "Truncating replacement of {} bytes to {} bytes",
+            replacement_len,
+            size
+        );
+    }
+    if let Some(device) = device {
+        report!(args, "Graft touches sectors:");
+        for sector in device.sectors_touched(&Range::from_u64(written.start, size))? {
+            report!(
+                args,
+                "  {:#010x}..{:#010x} ({} bytes)",
+                sector.start,
+                sector.end,
+                sector.end - sector.start
+            );
+        }
+    }
+    Ok(())
+}
+
 fn run() -> Result<()> {
     env_logger::init();
 
@@ -68,6 +489,11 @@ fn run() -> Result<()> {
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
 
+    check_stdin(&args)?;
+    if args.flag_in_place && ops::is_stdio(&args.arg_input) {
+        return Err(Error::Parse("--in-place requires an input file".to_owned()));
+    }
+
     // check arguments
     if args.flag_version {
         println!("{} {}", NAME, VERSION);
@@ -79,18 +505,33 @@ fn run() -> Result<()> {
         // command stance
         let range = args
             .flag_range
-            .ok_or_else(|| format_err!("Missing range for stance"))?;
+            .clone()
+            .ok_or_else(|| Error::Parse("Missing range for stance".to_owned()))?;
+        let device = device(&args)?;
+        if let Some(sectors) = sectors(&args, device.as_ref()) {
+            sectors.check_range("Range", &range)?;
+        }
+
+        if streamable(&args)? {
+            let (input, len) = open_streamed(&args.arg_input)?;
+            let mut sources = [input];
+            let fill_pattern = fill_pattern_seeded(&args, device.as_ref(), || {
+                stream::content_seed(&mut sources)
+            })?;
 
-        // guess meta_in from file
-        let path = args.arg_input;
-        let meta_in = MetaInfo::from_file_extension(&path)
-            .or_else::<Error, _>(|_err: Error| MetaInfo::from_content(&path))?;
+            let mut out_bytes = StreamedBytes::source(0, len);
+            out_bytes.stance(&range.start, &range.size)?;
+            prepare_streamed_output(&mut out_bytes, &args, device.as_ref())?;
+            return target(&args).save_streamed(&out_bytes, &mut sources, &fill_pattern);
+        }
 
         // load the input file
-        let mut in_bytes = AnnotatedBytes::load(&path, meta_in)?;
+        let (mut in_bytes, meta_in) = load_input(&args, 0)?;
+        let fill_pattern = fill_pattern(&args, device.as_ref(), &[&in_bytes])?;
 
         // do the cutting
-        in_bytes.stance(range.start, range.size);
+        in_bytes.stance(range.start, range.size)?;
+        prepare_output(&mut in_bytes, &args, device.as_ref(), &fill_pattern)?;
 
         // save output file
         let meta_out = args.flag_file_format.unwrap_or(meta_in);
@@ -100,28 +541,42 @@ fn run() -> Result<()> {
     } else if args.cmd_stitch {
         // command stitch binaries together
 
+        let device = device(&args)?;
+        for offset in args.flag_offset.iter() {
+            if let Some(sectors) = sectors(&args, device.as_ref()) {
+                sectors.check_offset("Offset", offset)?;
+            }
+            if let Some(device) = &device {
+                device.check_write_offset("Offset", offset)?;
+            }
+        }
+
         // construct vec <AnnotatedBytes>
-        let stitch_vec = args.flag_input.into_iter().try_fold(
+        let stitch_vec = (0..args.flag_input.len()).try_fold(
             // Vec::<AnnotatedBytes>::with_capacity(10),
             Vec::<AnnotatedBytes>::new(),
-            |mut collection, path| {
-                let meta_in: MetaInfo = MetaInfo::from_file_extension(&path)
-                    .or_else::<Error, _>(|_err: Error| MetaInfo::from_content(&path))?;
-                let bytes = AnnotatedBytes::load(&path, meta_in)?;
+            |mut collection, idx| {
+                let (bytes, _meta_in) = load_input(&args, idx)?;
                 collection.push(bytes);
                 Ok::<_, Error>(collection)
             },
         )?;
 
+        let fill_pattern = fill_pattern(
+            &args,
+            device.as_ref(),
+            &stitch_vec.iter().collect::<Vec<_>>(),
+        )?;
+
         // construct vec <(AnnotatedBytes, ByteOffset)>
         let stitch_vec = stitch_vec
             .into_iter()
-            .zip(args.flag_offset.into_iter())
+            .zip(args.flag_offset.iter().cloned())
             .collect();
 
         // do the stitching
-        let out_bytes =
-            AnnotatedBytes::stitch(stitch_vec, args.flag_fill_pattern.unwrap_or_default())?;
+        let mut out_bytes = AnnotatedBytes::stitch(stitch_vec, fill_pattern.clone())?;
+        prepare_output(&mut out_bytes, &args, device.as_ref(), &fill_pattern)?;
 
         // save stitched output file
         // for consistent behaviour, should we also use the first meta_in as meta_out?
@@ -134,27 +589,425 @@ fn run() -> Result<()> {
 
         let range = args
             .flag_range
-            .ok_or_else(|| format_err!("Missing range for graft"))?;
+            .clone()
+            .ok_or_else(|| Error::Parse("Missing range for graft".to_owned()))?;
+        let device = device(&args)?;
+        if let Some(sectors) = sectors(&args, device.as_ref()) {
+            sectors.check_range("Graft region", &range)?;
+        }
+        if let Some(device) = &device {
+            device.check_write_offset("Graft region start", &range.start)?;
+        }
+
+        let graft_mode = args.flag_graft_mode.unwrap_or_default();
 
-        // guess meta_in from files
-        let path_in = args.arg_input;
-        let path_graft = args.flag_replace;
-        let meta_in: MetaInfo = MetaInfo::from_file_extension(&path_in)
-            .or_else::<Error, _>(|_err: Error| MetaInfo::from_content(&path_in))?;
-        let meta_graft: MetaInfo = MetaInfo::from_file_extension(&path_graft)
-            .or_else::<Error, _>(|_err: Error| MetaInfo::from_content(&path_graft))?;
+        if streamable(&args)? {
+            let (input, len) = open_streamed(&args.arg_input)?;
+            let (replace, replace_len) = open_streamed(&args.flag_replace)?;
+            let mut sources = [input, replace];
+            let fill_pattern = fill_pattern_seeded(&args, device.as_ref(), || {
+                stream::content_seed(&mut sources)
+            })?;
+
+            let mut out_bytes = StreamedBytes::source(0, len);
+            out_bytes.graft(
+                StreamedBytes::source(1, replace_len),
+                &range.start,
+                &range.size,
+                graft_mode,
+            )?;
+            prepare_streamed_output(&mut out_bytes, &args, device.as_ref())?;
+            report_graft(&args, device.as_ref(), &range, graft_mode, len, replace_len)?;
+            return target(&args).save_streamed(&out_bytes, &mut sources, &fill_pattern);
+        }
 
         // open input files
-        let mut in_bytes = AnnotatedBytes::load(&path_in, meta_in)?;
-        let graft_bytes = AnnotatedBytes::load(&path_graft, meta_graft)?;
+        let (mut in_bytes, meta_in) = load_input(&args, 0)?;
+        let (graft_bytes, _meta_graft) = load_input(&args, 1)?;
+        let fill_pattern = fill_pattern(&args, device.as_ref(), &[&in_bytes, &graft_bytes])?;
+        let len = in_bytes.bytes.len() as u64;
+        let replace_len = graft_bytes.bytes.len() as u64;
 
         // put graft_bytes into in_bytes
         in_bytes.graft(
             graft_bytes,
-            range.start,
-            range.size,
-            args.flag_fill_pattern.unwrap_or_default(),
+            range.start.clone(),
+            range.size.clone(),
+            fill_pattern.clone(),
+            graft_mode,
         )?;
+        prepare_output(&mut in_bytes, &args, device.as_ref(), &fill_pattern)?;
+        report_graft(&args, device.as_ref(), &range, graft_mode, len, replace_len)?;
+
+        // save output file
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+        target(&args).save(in_bytes, meta_in, meta_out)?;
+
+        Ok(())
+    } else if args.cmd_patch {
+        // command patch
+        if streamable(&args)? {
+            let (input, len) = open_streamed(&args.arg_input)?;
+            let mut out_bytes = StreamedBytes::source(0, len);
+            for (at, value) in args.flag_at.iter().zip(args.flag_value.iter()) {
+                out_bytes.patch(at, &value.bytes)?;
+            }
+            return target(&args).save_streamed(&out_bytes, &mut [input], &FillPattern::default());
+        }
+
+        let (mut in_bytes, meta_in) = load_input(&args, 0)?;
+
+        for (at, value) in args.flag_at.iter().zip(args.flag_value.iter()) {
+            in_bytes.patch(at, &value.bytes)?;
+        }
+
+        // save output file
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+        target(&args).save(in_bytes, meta_in, meta_out)?;
+
+        Ok(())
+    } else if args.cmd_insert || args.cmd_delete {
+        // commands insert and delete
+        let (mut in_bytes, meta_in) = load_input(&args, 0)?;
+        let original_len = in_bytes.bytes.len();
+
+        if args.cmd_insert {
+            let at = args
+                .flag_at
+                .first()
+                .ok_or_else(|| Error::Parse("Missing offset for insert".to_owned()))?;
+            match (&args.flag_from, args.flag_value.first()) {
+                (Some(_path), _) => {
+                    let (data, _meta) = load_input(&args, 1)?;
+                    in_bytes.insert(at, &data.bytes)?;
+                }
+                (None, Some(value)) => in_bytes.insert(at, &value.bytes)?,
+                (None, None) => return Err(Error::Parse("Missing data to insert".to_owned())),
+            }
+        } else {
+            let range = args
+                .flag_range
+                .as_ref()
+                .ok_or_else(|| Error::Parse("Missing range for delete".to_owned()))?;
+            in_bytes.delete(&range.start, &range.size)?;
+        }
+
+        if args.flag_keep_size {
+            let fill_pattern = fill_pattern(&args, None, &[&in_bytes])?;
+            in_bytes.resize(original_len, &fill_pattern)?;
+        }
+
+        // save output file
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+        in_bytes.save(&args.flag_output, meta_out)?;
+
+        Ok(())
+    } else if args.cmd_diff {
+        // command diff
+        let (a, _meta_a) = load_input(&args, 0)?;
+        let (b, _meta_b) = load_input(&args, 1)?;
+
+        let layout = match (&args.flag_layout, &args.flag_elf) {
+            (Some(path), _) => Layout::load(path)?,
+            (None, Some(path)) => {
+                let base = match &args.flag_base {
+                    Some(base) => base.as_u64()?,
+                    None => 0,
+                };
+                Layout::from_elf(path, base)?
+            }
+            (None, None) => Layout::default(),
+        };
+
+        if a.bytes.len() != b.bytes.len() {
+            println!(
+                "Sizes differ: {:?} has {} bytes, {:?} has {} bytes",
+                &args.arg_input,
+                a.bytes.len(),
+                &args.arg_other,
+                b.bytes.len()
+            );
+        }
+
+        let differences = diff::differences(&a.bytes, &b.bytes, args.flag_gap.as_usize()?);
+        for range in differences.iter() {
+            let names = layout.names_overlapping(range.start as u64, range.end as u64);
+            print!(
+                "{:#010x}..{:#010x} ({} bytes)",
+                range.start,
+                range.end,
+                range.len()
+            );
+            if names.is_empty() {
+                println!();
+            } else {
+                println!(" {}", names.join(", "));
+            }
+            if args.flag_hexdump {
+                print!("{}", diff::hexdump_side_by_side(&a.bytes, &b.bytes, range));
+            }
+        }
+
+        if differences.is_empty() {
+            Ok(())
+        } else {
+            println!("{} differing ranges", differences.len());
+            std::process::exit(1)
+        }
+    } else if args.cmd_delta && args.cmd_create {
+        // command delta create
+        let (source, _meta_source) = load_input(&args, 0)?;
+        let (target, _meta_target) = load_input(&args, 1)?;
+
+        let delta = delta::create(&source.bytes, &target.bytes);
+        report!(
+            args,
+            "Delta of {} bytes for a target of {} bytes",
+            delta.len(),
+            target.bytes.len()
+        );
+        ops::write_output(&args.flag_output, &delta)?;
+
+        Ok(())
+    } else if args.cmd_delta && args.cmd_apply {
+        // command delta apply
+        let (source, meta_in) = load_input(&args, 0)?;
+        let delta = ops::read_input(&args.flag_delta)?;
+
+        let mut out_bytes = AnnotatedBytes::new();
+        out_bytes
+            .bytes
+            .extend_from_slice(&delta::apply(&source.bytes, &delta)?);
+
+        // save output file
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+        out_bytes.save(&args.flag_output, meta_out)?;
+
+        Ok(())
+    } else if args.cmd_dump {
+        // command dump
+        let (in_bytes, _meta_in) = load_input(&args, 0)?;
+        let device = device(&args)?;
+
+        let base = args
+            .flag_base
+            .as_ref()
+            .map(ByteOffset::as_u64)
+            .transpose()?;
+        let base = in_bytes.resolve_base(base, device.as_ref())?.unwrap_or(0);
+        let (start, end) = match &args.flag_range {
+            Some(range) => (range.start.as_usize()?, range.end()?.as_usize()?),
+            None => (0, in_bytes.bytes.len()),
+        };
+        let bytes = in_bytes.bytes.get(start..end).ok_or_else(|| {
+            Error::OutOfBounds(format!(
+                "Range {:#x}..{:#x} exceeds the file size {}",
+                start,
+                end,
+                in_bytes.bytes.len()
+            ))
+        })?;
+
+        let format = DumpFormat {
+            width: args.flag_width.unwrap_or(16),
+            group: args.flag_group,
+            endianness: args.flag_endianness,
+            collapse: !args.flag_no_collapse,
+        };
+        let address = base.checked_add(start as u64).ok_or_else(|| {
+            Error::OutOfBounds(format!("Address of {:#x} exceeds the 64 bit range", start))
+        })?;
+        print!("{}", dump::hexdump(bytes, address, &format)?);
+
+        Ok(())
+    } else if args.cmd_info {
+        // command info
+        let content = ops::read_input(&args.arg_input)?;
+        let (meta_in, detection) = input_format(&args, 0, &content)?;
+        let in_bytes = AnnotatedBytes::parse(&content, meta_in)?;
+        let segments = in_bytes.segments()?;
+
+        let info = info::inspect(
+            &format!("{:?}", meta_in),
+            &detection.to_string(),
+            &in_bytes.bytes,
+            in_bytes.base_address,
+            &segments,
+            args.flag_block_size.as_usize()?,
+        )?;
+        if args.flag_json {
+            let json = serde_json::to_string_pretty(&info)
+                .map_err(|e| Error::Format(format!("Failed to serialize the summary: {}", e)))?;
+            println!("{}", json);
+        } else {
+            print!("{}", info);
+        }
+
+        Ok(())
+    } else if args.cmd_find {
+        // command find
+        let (in_bytes, _meta_in) = load_input(&args, 0)?;
+        let device = device(&args)?;
+        let pattern = args
+            .flag_pattern
+            .as_ref()
+            .ok_or_else(|| Error::Parse("Missing pattern to find".to_owned()))?;
+
+        let base = args
+            .flag_base
+            .as_ref()
+            .map(ByteOffset::as_u64)
+            .transpose()?;
+        let base = in_bytes.resolve_base(base, device.as_ref())?;
+
+        let matches = pattern.find_all(&in_bytes.bytes, args.flag_alignment.as_usize()?);
+        for found in matches.iter() {
+            match base {
+                Some(base) => {
+                    let address = base.checked_add(found.start as u64).ok_or_else(|| {
+                        Error::OutOfBounds(format!(
+                            "Address of the match at {:#x} exceeds the 64 bit range",
+                            found.start
+                        ))
+                    })?;
+                    println!("{:#x} {:#010x}", found.start, address)
+                }
+                None => println!("{:#x}", found.start),
+            }
+        }
+
+        if matches.is_empty() {
+            std::process::exit(1)
+        }
+        Ok(())
+    } else if args.cmd_replace {
+        // command replace
+        let (mut in_bytes, meta_in) = load_input(&args, 0)?;
+        let pattern = args
+            .flag_pattern
+            .as_ref()
+            .ok_or_else(|| Error::Parse("Missing pattern to replace".to_owned()))?;
+        let with = args
+            .flag_with
+            .as_ref()
+            .ok_or_else(|| Error::Parse("Missing replacement".to_owned()))?;
+
+        let matches =
+            pattern.find_non_overlapping(&in_bytes.bytes, args.flag_alignment.as_usize()?);
+        if let Some(count) = args.flag_count {
+            if matches.len() != count {
+                return Err(Error::Format(format!(
+                    "Expected {} matches but found {}",
+                    count,
+                    matches.len()
+                )));
+            }
+        }
+        in_bytes.replace(&matches, &with.bytes, args.flag_resize)?;
+        matches
+            .iter()
+            .for_each(|found| report!(args, "{:#x}", found.start));
+
+        // save output file
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+        in_bytes.save(&args.flag_output, meta_out)?;
+
+        Ok(())
+    } else if args.cmd_split {
+        // command split
+        let (in_bytes, meta_in) = load_input(&args, 0)?;
+        let chunk_size = args
+            .flag_chunk_size
+            .as_ref()
+            .ok_or_else(|| Error::Parse("Missing chunk size".to_owned()))?
+            .as_usize()?;
+        let fill_pattern = fill_pattern(&args, None, &[&in_bytes])?;
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+
+        let pad = Some(&fill_pattern).filter(|_| args.flag_pad_last);
+        let (chunks, manifest) = split::split(&in_bytes, chunk_size, pad, &args.flag_output)?;
+        for (chunk, entry) in chunks.into_iter().zip(manifest.chunks.iter()) {
+            println!(
+                "{} {:#x} {} {:#010x}",
+                entry.file.display(),
+                entry.offset,
+                entry.size,
+                entry.crc32
+            );
+            chunk.save(&entry.file, meta_out)?;
+        }
+
+        if let Some(path) = &args.flag_manifest {
+            manifest.save(path)?;
+        }
+
+        Ok(())
+    } else if args.cmd_deinterleave {
+        // command deinterleave
+        let (in_bytes, meta_in) = load_input(&args, 0)?;
+        let ways = args
+            .flag_ways
+            .ok_or_else(|| Error::Parse("Missing number of ways".to_owned()))?;
+
+        let lanes = in_bytes.deinterleave(ways, args.flag_width.unwrap_or(1))?;
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+        for (index, lane) in lanes.into_iter().enumerate() {
+            lane.save(&split::indexed_path(&args.flag_output, index)?, meta_out)?;
+        }
+
+        Ok(())
+    } else if args.cmd_interleave {
+        // command interleave
+        let mut meta_out = args.flag_file_format;
+        let mut lanes = Vec::new();
+        for idx in 0..args.flag_input.len() {
+            let (lane, meta_in) = load_input(&args, idx)?;
+            meta_out = meta_out.or(Some(meta_in));
+            lanes.push(lane);
+        }
+
+        let interleaved = AnnotatedBytes::interleave(&lanes, args.flag_width.unwrap_or(1))?;
+        interleaved.save(&args.flag_output, meta_out.unwrap_or_default())?;
+
+        Ok(())
+    } else if args.cmd_swap {
+        // command swap
+        let (mut in_bytes, meta_in) = load_input(&args, 0)?;
+        let width = args
+            .flag_width
+            .ok_or_else(|| Error::Parse("Missing word width".to_owned()))?;
+
+        let fill_pattern = fill_pattern(&args, None, &[&in_bytes])?;
+        swap(
+            &mut in_bytes,
+            &args,
+            args.flag_range.as_ref(),
+            width,
+            &fill_pattern,
+        )?;
+
+        // save output file
+        let meta_out = args.flag_file_format.unwrap_or(meta_in);
+        in_bytes.save(&args.flag_output, meta_out)?;
+
+        Ok(())
+    } else if args.cmd_transform {
+        // command transform
+        let (mut in_bytes, meta_in) = load_input(&args, 0)?;
+        let len = in_bytes.bytes.len();
+        let (start, end) = match &args.flag_range {
+            Some(range) => (range.start.as_usize()?, range.end()?.as_usize()?),
+            None => (0, len),
+        };
+        let bytes = in_bytes.bytes.get_mut(start..end).ok_or_else(|| {
+            Error::OutOfBounds(format!(
+                "Range {:#x}..{:#x} exceeds the file size {}",
+                start, end, len
+            ))
+        })?;
+        for transform in args.flag_op.iter() {
+            transform.apply(bytes)?;
+        }
 
         // save output file
         let meta_out = args.flag_file_format.unwrap_or(meta_in);
@@ -162,11 +1015,16 @@ fn run() -> Result<()> {
 
         Ok(())
     } else {
-        Err(format_err!("No idea what you were thinking.."))
+        Err(Error::Parse("No idea what you were thinking..".to_owned()))
     }
 }
 
-quick_main!(run);
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -187,7 +1045,7 @@ mod test {
             ]
         };
         let args: Args = Docopt::new(USAGE)
-            .and_then(|d| d.argv(argv().into_iter()).deserialize())
+            .and_then(|d| d.argv(argv()).deserialize())
             .unwrap_or_else(|e| e.exit());
 
         assert!(args.cmd_stance);
@@ -214,7 +1072,7 @@ mod test {
             ]
         };
         let args: Args = Docopt::new(USAGE)
-            .and_then(|d| d.argv(argv().into_iter()).deserialize())
+            .and_then(|d| d.argv(argv()).deserialize())
             .unwrap_or_else(|e| e.exit());
 
         assert!(args.cmd_stance);
@@ -241,7 +1099,7 @@ mod test {
             ]
         };
         let args: std::result::Result<Args, docopt::Error> =
-            Docopt::new(USAGE).and_then(|d| d.argv(argv().into_iter()).deserialize());
+            Docopt::new(USAGE).and_then(|d| d.argv(argv()).deserialize());
         assert!(args.is_err());
     }
 
@@ -259,7 +1117,7 @@ mod test {
             ]
         };
         let args: std::result::Result<Args, docopt::Error> =
-            Docopt::new(USAGE).and_then(|d| d.argv(argv().into_iter()).deserialize());
+            Docopt::new(USAGE).and_then(|d| d.argv(argv()).deserialize());
         assert!(args.is_err());
     }
 
@@ -271,7 +1129,7 @@ mod test {
             ]
         };
         let args: Args = Docopt::new(USAGE)
-            .and_then(|d| d.argv(argv().into_iter()).deserialize())
+            .and_then(|d| d.argv(argv()).deserialize())
             .unwrap_or_else(|e| e.exit());
 
         assert!(args.cmd_stance);
@@ -284,6 +1142,531 @@ mod test {
         );
     }
 
+    #[test]
+    fn docopt_expression() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "stance",
+                "--range",
+                "(16Ki*3)+align_up(4000, 4Ki)",
+                "--output",
+                "a",
+                "in",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert_eq!(
+            args.flag_range,
+            Some(Range::new(
+                ByteOffset::new(48 * 1024, Magnitude::Unit),
+                ByteOffset::new(4096, Magnitude::Unit)
+            ))
+        );
+    }
+
+    #[test]
+    fn docopt_patch() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "patch",
+                "--at",
+                "0x100",
+                "--value",
+                "u16be:0x1234",
+                "--at",
+                "0x200",
+                "--value",
+                "strz:v1.2",
+                "--output",
+                "out.bin",
+                "in.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_patch);
+        assert_eq!(
+            args.flag_at,
+            vec![
+                ByteOffset::new(256, Magnitude::Unit),
+                ByteOffset::new(512, Magnitude::Unit)
+            ]
+        );
+        assert_eq!(args.flag_value[0].bytes, vec![0x12, 0x34]);
+        assert_eq!(args.flag_value[1].bytes, b"v1.2\0".to_vec());
+    }
+
+    #[test]
+    fn docopt_diff() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "diff",
+                "--gap",
+                "16",
+                "--elf",
+                "fw.elf",
+                "--base",
+                "0x08000000",
+                "--hexdump",
+                "old.bin",
+                "new.hex",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_diff);
+        assert!(args.flag_hexdump);
+        assert_eq!(args.flag_gap.as_u64().unwrap(), 16);
+        assert_eq!(
+            args.flag_base,
+            Some(ByteOffset::new(0x0800_0000, Magnitude::Unit))
+        );
+        assert_eq!(args.flag_elf, Some(PathBuf::from("fw.elf")));
+        assert_eq!(args.arg_input, PathBuf::from("old.bin"));
+        assert_eq!(args.arg_other, PathBuf::from("new.hex"));
+    }
+
+    #[test]
+    fn docopt_delta() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "delta",
+                "create",
+                "old.bin",
+                "new.bin",
+                "-o",
+                "patch.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_delta && args.cmd_create && !args.cmd_apply);
+        assert_eq!(args.flag_output, PathBuf::from("patch.bin"));
+        assert_eq!(args.arg_other, PathBuf::from("new.bin"));
+
+        let argv = || {
+            vec![
+                "scalpel",
+                "delta",
+                "apply",
+                "--delta",
+                "patch.bin",
+                "-o",
+                "new.bin",
+                "old.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_delta && args.cmd_apply);
+        assert_eq!(args.flag_delta, PathBuf::from("patch.bin"));
+        assert_eq!(args.arg_input, PathBuf::from("old.bin"));
+    }
+
+    #[test]
+    fn docopt_dump() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "dump",
+                "--range",
+                "0x100+64",
+                "--group",
+                "4",
+                "--endianness",
+                "little",
+                "firmware.hex",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_dump);
+        assert_eq!(args.flag_width, None);
+        assert_eq!(args.flag_group, 4);
+        assert_eq!(args.flag_endianness, Endianness::Little);
+        assert!(!args.flag_no_collapse);
+        assert_eq!(args.flag_base, None);
+    }
+
+    #[test]
+    fn docopt_stdio() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "stance",
+                "--range",
+                "0..1Ki",
+                "--input-format",
+                "hex",
+                "--file-format",
+                "bin",
+                "-o",
+                "-",
+                "-",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_stance);
+        assert!(ops::is_stdio(&args.arg_input));
+        assert!(ops::is_stdio(&args.flag_output));
+        assert_eq!(args.flag_input_format, vec![MetaInfo::IntelHex]);
+        assert_eq!(args.flag_file_format, Some(MetaInfo::Bin));
+        assert_eq!(
+            input_format(&args, 0, b"").unwrap(),
+            (MetaInfo::IntelHex, Detection::Explicit)
+        );
+        assert!(check_stdin(&args).is_ok());
+    }
+
+    #[test]
+    fn docopt_input_formats() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "stitch",
+                "--input",
+                "boot.bin",
+                "--offset",
+                "0",
+                "--input",
+                "app.txt",
+                "--offset",
+                "64Ki",
+                "--input-format",
+                "bin",
+                "--input-format",
+                "hex",
+                "--output",
+                "stitched.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_stitch);
+        assert_eq!(
+            args.flag_input_format,
+            vec![MetaInfo::Bin, MetaInfo::IntelHex]
+        );
+        assert_eq!(input_format(&args, 1, b"").unwrap().0, MetaInfo::IntelHex);
+        assert_eq!(input_format(&args, 0, b"").unwrap().0, MetaInfo::Bin);
+    }
+
+    #[test]
+    fn docopt_in_place() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "patch",
+                "--at",
+                "0x200",
+                "--value",
+                "u32le:42",
+                "--in-place",
+                "--backup-suffix",
+                ".orig",
+                "firmware.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_patch);
+        assert!(args.flag_in_place);
+        assert_eq!(args.flag_backup_suffix, Some(".orig".to_owned()));
+        assert_eq!(args.arg_input, PathBuf::from("firmware.bin"));
+
+        let argv = vec![
+            "scalpel",
+            "graft",
+            "--replace",
+            "app.bin",
+            "--range",
+            "0..1Ki",
+            "--in-place",
+            "--output",
+            "out.bin",
+            "firmware.bin",
+        ];
+        assert!(Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv).deserialize::<Args>())
+            .is_err());
+    }
+
+    #[test]
+    fn device_sectors() {
+        let args = |argv: Vec<&str>| -> Args {
+            Docopt::new(USAGE)
+                .and_then(|d| d.argv(argv).deserialize())
+                .unwrap_or_else(|e| e.exit())
+        };
+        let args = args(vec![
+            "scalpel",
+            "graft",
+            "--replace",
+            "config.bin",
+            "--range",
+            "16Ki+8Ki",
+            "--device",
+            "stm32f407vg",
+            "--output",
+            "out.bin",
+            "firmware.bin",
+        ]);
+        let device = device(&args).unwrap();
+        let sectors = sectors(&args, device.as_ref()).expect("Missing device sectors");
+        let range = args.flag_range.clone().unwrap();
+        assert!(sectors.check_range("Graft region", &range).is_err());
+        assert!(sectors
+            .check_range("Graft region", &Range::from_u64(0x4000, 0x4000))
+            .is_ok());
+    }
+
+    #[test]
+    fn docopt_info() {
+        let argv = || vec!["scalpel", "info", "--json", "firmware.hex"];
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_info);
+        assert!(args.flag_json);
+        assert_eq!(args.flag_block_size.as_u64().unwrap(), 4096);
+    }
+
+    #[test]
+    fn docopt_find() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "find",
+                "--pattern",
+                "hex:DE AD ?? EF",
+                "--alignment",
+                "4",
+                "fw.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_find);
+        assert_eq!(
+            args.flag_pattern,
+            Some(Pattern::Bytes(vec![
+                Some(0xDE),
+                Some(0xAD),
+                None,
+                Some(0xEF)
+            ]))
+        );
+        assert_eq!(args.flag_alignment.as_u64().unwrap(), 4);
+    }
+
+    #[test]
+    fn docopt_replace() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "replace",
+                "--pattern",
+                "hex:DEADBEEF",
+                "--with",
+                "hex:01020304",
+                "--count",
+                "2",
+                "--output",
+                "out.bin",
+                "in.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_replace);
+        assert_eq!(args.flag_with.unwrap().bytes, vec![1, 2, 3, 4]);
+        assert_eq!(args.flag_count, Some(2));
+        assert!(!args.flag_resize);
+    }
+
+    #[test]
+    fn docopt_split() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "split",
+                "--chunk-size",
+                "4Ki",
+                "--pad-last",
+                "--manifest",
+                "parts.toml",
+                "--output",
+                "part_{index}.bin",
+                "fw.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_split);
+        assert!(args.flag_pad_last);
+        assert_eq!(
+            args.flag_chunk_size,
+            Some(ByteOffset::new(4, Magnitude::Ki))
+        );
+        assert_eq!(args.flag_manifest, Some(PathBuf::from("parts.toml")));
+        assert_eq!(args.flag_output, PathBuf::from("part_{index}.bin"));
+    }
+
+    #[test]
+    fn docopt_interleave() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "interleave",
+                "--input",
+                "even.bin",
+                "--input",
+                "odd.bin",
+                "--width",
+                "2",
+                "--output",
+                "rom.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_interleave);
+        assert_eq!(args.flag_input.len(), 2);
+        assert_eq!(args.flag_width, Some(2));
+
+        let argv = || {
+            vec![
+                "scalpel",
+                "deinterleave",
+                "--ways",
+                "2",
+                "--output",
+                "rom_{index}.bin",
+                "rom.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_deinterleave);
+        assert_eq!(args.flag_ways, Some(2));
+        assert_eq!(args.flag_width, None);
+    }
+
+    #[test]
+    fn docopt_swap() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "swap",
+                "--width",
+                "4",
+                "--range",
+                "0x100..0x200",
+                "--output",
+                "out.bin",
+                "in.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_swap);
+        assert_eq!(args.flag_width, Some(4));
+        assert!(!args.flag_swap_pad);
+
+        let argv = || {
+            vec![
+                "scalpel",
+                "stance",
+                "--range",
+                "0..1Ki",
+                "--swap",
+                "2",
+                "--swap-pad",
+                "--output",
+                "out.bin",
+                "in.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_stance);
+        assert_eq!(args.flag_swap, Some(2));
+        assert!(args.flag_swap_pad);
+    }
+
+    #[test]
+    fn docopt_transform() {
+        let argv = || {
+            vec![
+                "scalpel",
+                "transform",
+                "--op",
+                "xor:hex:A55A",
+                "--op",
+                "invert",
+                "--op",
+                "bit-reverse",
+                "--range",
+                "16..32",
+                "--output",
+                "out.bin",
+                "in.bin",
+            ]
+        };
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv()).deserialize())
+            .unwrap_or_else(|e| e.exit());
+
+        assert!(args.cmd_transform);
+        assert_eq!(
+            args.flag_op,
+            vec![
+                Transform::Xor(vec![0xA5, 0x5A]),
+                Transform::Invert,
+                Transform::BitReverse
+            ]
+        );
+    }
+
     #[test]
     fn docopt_byteoffset() {
         let argv = || {
@@ -302,12 +1685,16 @@ mod test {
                 "bytes",
                 "--offset",
                 "1Ki",
+                "--input",
+                "bytes",
+                "--offset",
+                "0x08000000+64Ki-0x200",
                 "--output",
                 "stance.bin",
             ]
         };
         let args: Args = Docopt::new(USAGE)
-            .and_then(|d| d.argv(argv().into_iter()).deserialize())
+            .and_then(|d| d.argv(argv()).deserialize())
             .unwrap_or_else(|e| e.exit());
 
         let mut offset_it = args.flag_offset.iter();
@@ -323,5 +1710,9 @@ mod test {
             offset_it.next().unwrap(),
             &ByteOffset::new(1, Magnitude::Ki)
         );
+        assert_eq!(
+            offset_it.next().unwrap(),
+            &ByteOffset::new(0x0800_0000 + 0x1_0000 - 0x200, Magnitude::Unit)
+        );
     }
 }
@@ -1,59 +1,486 @@
 use crate::byte_offset::*;
-use crate::intelhex::{convert_hex2bin, write_bin_as_hex_to_file};
-use bytes::BytesMut;
+use crate::device::DeviceProfile;
+use crate::intelhex::{convert_bin2hex, convert_hex2bin, is_record};
+use crate::sector::SectorMap;
+use crate::stream::Named;
+use bytes::{Bytes, BytesMut};
 use log::warn;
-use rand::Rng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::de;
 use serde_derive::Deserialize;
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::vec::Vec;
-use tree_magic;
 
-use failure::{format_err, Error};
+use crate::error::{Error, Result};
 
-pub type Result<X> = std::result::Result<X, Error>;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Endianness {
+    Little,
+    Big,
+}
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum FillPattern {
     Random,
+    /// Reproducible random bytes, `random:<seed>`.
+    ///
+    /// The byte at offset `n` is byte `n` of the ChaCha20 stream of
+    /// `rand_chacha::ChaCha20Rng::seed_from_u64(seed)`.
+    SeededRandom(u64),
+    #[default]
     Zero,
     One,
+    /// A single repeated byte, `byte:0xA5`.
+    Byte(u8),
+    /// A repeated byte sequence, from `pattern:DEADBEEF` or `file:<path>`.
+    Sequence(Vec<u8>),
+    /// Each word holds its own offset, `address:u32le`.
+    Address {
+        width: usize,
+        endianness: Endianness,
+    },
+}
+
+impl FillPattern {
+    /// Grow `bytes` to `len` with the pattern, never shrinks.
+    ///
+    /// Patterns are anchored at offset 0 of `bytes`, so the padding at any
+    /// offset does not depend on where the padding started.
+    pub fn fill(&self, bytes: &mut BytesMut, len: usize) -> Result<()> {
+        if len <= bytes.len() {
+            return Ok(());
+        }
+        let padding = self.generate(bytes.len(), len - bytes.len())?;
+        bytes.extend_from_slice(&padding);
+        Ok(())
+    }
+
+    /// The `len` bytes of the pattern at offset `start`.
+    pub fn generate(&self, start: usize, len: usize) -> Result<Vec<u8>> {
+        let end = start + len;
+        let padding = match self {
+            FillPattern::Zero => vec![0x00; len],
+            FillPattern::One => vec![0xFF; len],
+            FillPattern::Byte(value) => vec![*value; len],
+            FillPattern::Random => {
+                let mut padding = vec![0; len];
+                ::rand::thread_rng()
+                    .try_fill(&mut padding[..])
+                    .map_err(|e| {
+                        Error::Io("Failed to generate random bytes".to_owned(), e.into())
+                    })?;
+                padding
+            }
+            FillPattern::SeededRandom(seed) => {
+                let mut rng = ChaCha20Rng::seed_from_u64(*seed);
+                // the stream advances in 32 bit words, start at the word of `start`
+                rng.set_word_pos((start / 4) as u128);
+                let skip = start % 4;
+                let mut padding = vec![0; len + skip];
+                rng.fill_bytes(&mut padding[..]);
+                padding.split_off(skip)
+            }
+            FillPattern::Sequence(sequence) => (start..end)
+                .map(|idx| sequence[idx % sequence.len()])
+                .collect(),
+            FillPattern::Address { width, endianness } => {
+                let width = *width;
+                (start..end)
+                    .map(|idx| {
+                        let word = (idx - idx % width) as u64;
+                        let pos = idx % width;
+                        match endianness {
+                            Endianness::Little => word.to_le_bytes()[pos],
+                            Endianness::Big => word.to_be_bytes()[8 - width + pos],
+                        }
+                    })
+                    .collect()
+            }
+        };
+        Ok(padding)
+    }
+}
+
+impl FromStr for FillPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let (kind, value) = match s.find(':') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+        let fill_pattern = match (kind.to_lowercase().as_str(), value) {
+            ("random", None) => FillPattern::Random,
+            ("random", Some(seed)) => {
+                FillPattern::SeededRandom(ByteOffset::from_str(seed)?.as_u64()?)
+            }
+            ("zero", None) => FillPattern::Zero,
+            ("one", None) => FillPattern::One,
+            ("byte", Some(value)) => {
+                let value = ByteOffset::from_str(value)?.as_u64()?;
+                let value = u8::try_from(value)
+                    .map_err(|_| err!(Parse, "Fill byte {:#x} exceeds 0xFF", value))?;
+                FillPattern::Byte(value)
+            }
+            ("pattern", Some(value)) => FillPattern::Sequence(parse_hex_bytes(value)?),
+            ("file", Some(path)) => {
+                let sequence = std::fs::read(path)
+                    .map_err(|e| Error::Io(format!("Failed to open {:?}", path), e))?;
+                FillPattern::Sequence(sequence)
+            }
+            ("address", None) => FillPattern::Address {
+                width: 4,
+                endianness: Endianness::Little,
+            },
+            ("address", Some(word)) => {
+                let (width, endianness) = match word.to_lowercase().as_str() {
+                    "u8" => (1, Endianness::Little),
+                    "u16le" => (2, Endianness::Little),
+                    "u16be" => (2, Endianness::Big),
+                    "u32le" => (4, Endianness::Little),
+                    "u32be" => (4, Endianness::Big),
+                    "u64le" => (8, Endianness::Little),
+                    "u64be" => (8, Endianness::Big),
+                    _ => return Err(err!(Parse, "Unknown address word type {}", word)),
+                };
+                FillPattern::Address { width, endianness }
+            }
+            _ => return Err(err!(Parse, "Unknown fill pattern {}", s)),
+        };
+        if fill_pattern == FillPattern::Sequence(vec![]) {
+            return Err(err!(Parse, "Fill pattern {} is empty", s));
+        }
+        Ok(fill_pattern)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for FillPattern {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct FillPatternVisitor;
+
+        impl<'de> de::Visitor<'de> for FillPatternVisitor {
+            type Value = FillPattern;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expected a FillPattern")
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::std::result::Result<FillPattern, E>
+            where
+                E: de::Error,
+            {
+                FillPattern::from_str(value).map_err(|e| E::custom(e.to_string()))
+            }
+        }
+        deserializer.deserialize_str(FillPatternVisitor)
+    }
 }
 
-impl Default for FillPattern {
+/// Seed derived from the content of all `inputs`, stable across releases.
+///
+/// This is the 64 bit FNV-1a hash of the concatenation of the inputs, each
+/// followed by its length as `u64` little endian.
+pub fn content_seed<'a, I: IntoIterator<Item = &'a [u8]>>(inputs: I) -> u64 {
+    let mut seed = ContentSeed::new();
+    for input in inputs {
+        seed.update(input);
+        seed.end_input(input.len() as u64);
+    }
+    seed.seed()
+}
+
+/// Incremental `content_seed`, for inputs which are read in chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentSeed(u64);
+
+impl ContentSeed {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        ContentSeed(Self::FNV_OFFSET_BASIS)
+    }
+
+    /// Hash the next chunk of the current input.
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(Self::FNV_PRIME)
+        });
+    }
+
+    /// Finish the current input of `len` bytes.
+    pub fn end_input(&mut self, len: u64) {
+        self.update(&len.to_le_bytes());
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for ContentSeed {
     fn default() -> Self {
-        FillPattern::Zero
+        Self::new()
+    }
+}
+
+/// Parse a string of hex digit pairs, optionally prefixed with `0x`.
+/// Whitespace and `_` may be used as separator.
+pub fn parse_hex_bytes(s: &str) -> Result<Vec<u8>> {
+    let digits: String = s
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '_')
+        .collect();
+    // slicing below relies on single byte characters
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(err!(Parse, "Invalid hex digit {:?} in {}", c, s));
+    }
+    if digits.len() % 2 != 0 {
+        return Err(err!(Parse, "Odd number of hex digits in {}", s));
     }
+    (0..digits.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&digits[idx..idx + 2], 16)
+                .map_err(|e| err!(Parse, "Failed to parse hex {}: {}", s, e))
+        })
+        .collect()
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+/// Reverse the byte order within each word of `width` bytes of `words`,
+/// `width` is either 2, 4 or 8.
+pub fn swap_words(words: &mut [u8], width: usize) -> Result<()> {
+    if ![2, 4, 8].contains(&width) {
+        return Err(err!(
+            Parse,
+            "Word width {} is not supported, expected 2, 4 or 8",
+            width
+        ));
+    }
+    if words.len() % width != 0 {
+        return Err(err!(
+            Alignment,
+            "Size {} is not a multiple of the word width {}",
+            words.len(),
+            width
+        ));
+    }
+    words.chunks_mut(width).for_each(|word| word.reverse());
+    Ok(())
+}
+
+/// Behaviour of graft if the replacement is larger than the range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum GraftMode {
+    /// Fail, the range must be within the image and fit the replacement.
+    #[default]
+    Strict,
+    /// Grow the image, shifting subsequent bytes. A range beyond the end
+    /// of the image is padded with the fill pattern.
+    Grow,
+    /// Truncate the replacement to the range size. A range beyond the end
+    /// of the image is cut at the end of the image.
+    Truncate,
+}
+
+impl GraftMode {
+    /// Size of the range `start+size` which is grafted into an image of
+    /// `len` bytes, errors if the mode does not accept the range.
+    pub fn range_size(self, len: u64, start: u64, size: u64) -> Result<u64> {
+        let end = start.checked_add(size).ok_or_else(|| {
+            err!(
+                OutOfBounds,
+                "Graft range end exceeds the platform address space"
+            )
+        })?;
+        match self {
+            _ if end <= len => Ok(size),
+            GraftMode::Grow => Ok(size),
+            GraftMode::Truncate if start <= len => Ok(len - start),
+            _ => Err(err!(
+                OutOfBounds,
+                "Graft range {:#x}+{:#x} exceeds the image size {}",
+                start,
+                size,
+                len
+            )),
+        }
+    }
+
+    /// Region actually written by grafting `replacement_len` bytes into the
+    /// range `start+size` of an image of `len` bytes, shorter than the
+    /// replacement if it is truncated.
+    pub fn written(
+        self,
+        len: u64,
+        start: u64,
+        size: u64,
+        replacement_len: u64,
+    ) -> Result<Range<u64>> {
+        let size = self.range_size(len, start, size)?;
+        let size = match self {
+            GraftMode::Grow => std::cmp::max(size, replacement_len),
+            _ => size,
+        };
+        let end = start.checked_add(size).ok_or_else(|| {
+            err!(
+                OutOfBounds,
+                "Graft range end exceeds the platform address space"
+            )
+        })?;
+        Ok(start..end)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum MetaInfo {
     IntelHex,
+    #[default]
     Bin,
 }
 
-impl Default for MetaInfo {
-    fn default() -> Self {
-        MetaInfo::Bin
+impl FromStr for MetaInfo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bin" => Ok(MetaInfo::Bin),
+            "hex" | "intelhex" => Ok(MetaInfo::IntelHex),
+            _ => Err(err!(
+                Parse,
+                "Unknown file format {}, expected bin or hex",
+                s
+            )),
+        }
+    }
+}
+
+impl<'de> de::Deserialize<'de> for MetaInfo {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct MetaInfoVisitor;
+
+        impl<'de> de::Visitor<'de> for MetaInfoVisitor {
+            type Value = MetaInfo;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expected a MetaInfo")
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::std::result::Result<MetaInfo, E>
+            where
+                E: de::Error,
+            {
+                MetaInfo::from_str(value).map_err(|e| E::custom(e.to_string()))
+            }
+        }
+        deserializer.deserialize_str(MetaInfoVisitor)
     }
 }
 
+/// How the format of an input was determined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detection {
+    /// Given by `--input-format`.
+    Explicit,
+    Extension(String),
+    /// A signature at the start of the content.
+    Signature(&'static str),
+    /// No known signature, the content is a raw binary.
+    NoSignature,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Detection::Explicit => write!(f, "--input-format"),
+            Detection::Extension(ext) => write!(f, "file extension .{}", ext),
+            Detection::Signature(signature) => write!(f, "signature of {}", signature),
+            Detection::NoSignature => write!(f, "absence of a known signature"),
+        }
+    }
+}
+
+/// Bytes at the start of a file needed to detect its format.
+pub const SIGNATURE_LEN: usize = 4096;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+/// First and second magic number of a UF2 block, little endian.
+const UF2_MAGIC: &[u8] = b"UF2\n\x57\x51\x5d\x9e";
+
+/// A Motorola S-record line with a valid length and checksum.
+fn is_srecord(line: &str) -> bool {
+    // checked bytewise, the header may contain arbitrary content
+    let chars = line.as_bytes();
+    if chars.len() < 4
+        || chars[0] != b'S'
+        || !chars[1].is_ascii_digit()
+        || !chars[2..].iter().all(u8::is_ascii_hexdigit)
+    {
+        return false;
+    }
+    let bytes = match parse_hex_bytes(&line[2..]) {
+        Ok(bytes) => bytes,
+        _ => return false,
+    };
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    usize::from(bytes[0]) == bytes.len() - 1 && sum == 0xFF
+}
+
 impl MetaInfo {
-    #[allow(dead_code)]
-    pub fn from_header_bytes(first_bytes: &[u8]) -> Result<MetaInfo> {
-        match tree_magic::from_u8(first_bytes).as_str() {
-            "binary" => Ok(MetaInfo::Bin),
-            "ascii/text" => Ok(MetaInfo::IntelHex), // TODO actually attempt to parse maybe?
-            _ => Err(format_err!("Unsupported error type")),
+    /// Format of `path` starting with `header`, the first `SIGNATURE_LEN`
+    /// bytes, from its extension or else from the signature of the content.
+    pub fn detect(path: &Path, header: &[u8]) -> Result<(MetaInfo, Detection)> {
+        match Self::from_file_extension(path) {
+            Ok(meta_in) => {
+                let ext = path.extension().unwrap_or_default().to_string_lossy();
+                Ok((meta_in, Detection::Extension(ext.into_owned())))
+            }
+            Err(_) => Self::from_signature(header),
         }
     }
 
-    pub fn from_content(path: &Path) -> Result<MetaInfo> {
-        match tree_magic::from_filepath(path).as_str() {
-            "application/octet-stream" => Ok(MetaInfo::Bin),
-            "ascii/text" => Ok(MetaInfo::IntelHex),
-            _ => Err(format_err!("Unspupported File Type")),
+    /// Format from the signature at the start of the content. ELF, UF2 and
+    /// Motorola S-record files are recognized but not supported.
+    pub fn from_signature(header: &[u8]) -> Result<(MetaInfo, Detection)> {
+        if header.starts_with(ELF_MAGIC) {
+            return Err(err!(
+                Format,
+                "ELF files are not supported, extract the image with objcopy -O binary or -O ihex"
+            ));
+        }
+        if header.starts_with(UF2_MAGIC) {
+            return Err(err!(Format, "UF2 files are not supported"));
+        }
+
+        let first_line = header
+            .split(|byte| *byte == b'\n')
+            .map(|line| std::str::from_utf8(line).map(str::trim))
+            .find(|line| line.map(|line| !line.is_empty()).unwrap_or(true));
+        match first_line {
+            Some(Ok(line)) if is_record(line) => {
+                Ok((MetaInfo::IntelHex, Detection::Signature("IntelHex records")))
+            }
+            Some(Ok(line)) if is_srecord(line) => Err(err!(
+                Format,
+                "Motorola S-record files are not supported, convert them with objcopy -O ihex"
+            )),
+            _ => Ok((MetaInfo::Bin, Detection::NoSignature)),
         }
     }
 
@@ -61,73 +488,627 @@ impl MetaInfo {
         match path.extension().and_then(|ext| ext.to_str()) {
             Some("bin") => Ok(MetaInfo::Bin),
             Some("hex") => Ok(MetaInfo::IntelHex),
-            Some(ext) => Err(format_err!("Unsupported file extension {}", ext)),
-            None => Err(format_err!("File does not have an extension to guess")),
+            Some(ext) => Err(err!(Format, "Unsupported file extension {}", ext)),
+            None => Err(err!(Format, "File does not have an extension to guess")),
+        }
+    }
+}
+
+/// `-` stands for stdin or stdout.
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Content of `path`, `-` reads stdin.
+pub fn read_input(path: &Path) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    if is_stdio(path) {
+        std::io::stdin()
+            .lock()
+            .read_to_end(&mut content)
+            .map_err(|e| Error::Io("Failed to read stdin".to_owned(), e))?;
+    } else {
+        OpenOptions::new()
+            .read(true)
+            .open(path)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(|e| Error::Io(format!("Failed to read {:?}", path), e))?;
+    }
+    Ok(content)
+}
+
+/// Buffered output to a file, which is complete only after `commit`.
+///
+/// Regular files are written atomically by an `AtomicWriter`, so an error
+/// never leaves a truncated output behind. `-` writes to stdout, special
+/// files such as devices are written directly.
+pub enum Output {
+    Stdout(BufWriter<std::io::Stdout>),
+    Direct(BufWriter<File>, PathBuf),
+    Atomic(AtomicWriter),
+}
+
+impl Output {
+    pub fn create(path: &Path) -> Result<Self> {
+        if is_stdio(path) {
+            return Ok(Output::Stdout(BufWriter::new(std::io::stdout())));
+        }
+        match std::fs::metadata(path) {
+            Ok(metadata) if !metadata.is_file() => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .map_err(|e| Error::Io(format!("Failed to write {:?}", path), e))?;
+                Ok(Output::Direct(BufWriter::new(file), path.to_owned()))
+            }
+            _ => Ok(Output::Atomic(AtomicWriter::create(path)?)),
+        }
+    }
+
+    pub fn commit(self) -> Result<()> {
+        match self {
+            Output::Stdout(mut stdout) => stdout
+                .flush()
+                .map_err(|e| Error::Io("Failed to write stdout".to_owned(), e)),
+            Output::Direct(mut file, path) => file
+                .flush()
+                .map_err(|e| Error::Io(format!("Failed to write {:?}", path), e)),
+            Output::Atomic(writer) => writer.commit(),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Output::Stdout(stdout) => stdout,
+            Output::Direct(file, _) => file,
+            Output::Atomic(writer) => writer,
+        }
+    }
+}
+
+impl Named for Output {
+    fn name(&self) -> String {
+        match self {
+            Output::Stdout(_) => "stdout".to_owned(),
+            Output::Direct(_, path) => format!("{:?}", path),
+            Output::Atomic(writer) => writer.name(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+/// Writer to a temporary file in the directory of `path`, which replaces
+/// `path` on `commit`. The permissions of an existing `path` are kept, the
+/// temporary file is removed if the writer is dropped without `commit`.
+/// A symbolic link is followed, the file it points to is replaced.
+pub struct AtomicWriter {
+    path: PathBuf,
+    temp: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl AtomicWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let path = &std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        let name = path
+            .file_name()
+            .ok_or_else(|| err!(Parse, "Output {:?} is not a file", path))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp = dir.join(temp_name);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .map_err(|e| Error::Io(format!("Failed to create {:?}", temp), e))?;
+        let writer = AtomicWriter {
+            path: path.to_owned(),
+            temp,
+            file: Some(BufWriter::new(file)),
+        };
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&writer.temp, metadata.permissions())
+                .map_err(|e| Error::Io(format!("Failed to create {:?}", writer.temp), e))?;
         }
+        Ok(writer)
+    }
+
+    /// Replace `path` by the written content.
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().expect("Only taken by commit. qed");
+        let result = file
+            .into_inner()
+            .map_err(|e| Error::Io(format!("Failed to write {:?}", self.temp), e.into_error()))
+            .and_then(|file| {
+                file.sync_all()
+                    .map_err(|e| Error::Io(format!("Failed to write {:?}", self.temp), e))
+            })
+            .and_then(|_| {
+                std::fs::rename(&self.temp, &self.path)
+                    .map_err(|e| Error::Io(format!("Failed to replace {:?}", self.path), e))
+            });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+        result
+    }
+}
+
+impl Named for AtomicWriter {
+    fn name(&self) -> String {
+        format!("{:?}", self.temp)
     }
 }
 
-#[derive(Debug, Clone)]
+impl Write for AtomicWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file
+            .as_mut()
+            .expect("Only taken by commit. qed")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file
+            .as_mut()
+            .expect("Only taken by commit. qed")
+            .flush()
+    }
+}
+
+impl Drop for AtomicWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Write `content` to `path`, see `Output`.
+pub fn write_output(path: &Path, content: &[u8]) -> Result<()> {
+    let mut output = Output::create(path)?;
+    output
+        .write_all(content)
+        .map_err(|e| Error::Io(format!("Failed to write {:?}", path), e))?;
+    output.commit()
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct AnnotatedBytes {
     // TODO: reconsider name, they're not really annotated anymore?
     pub bytes: BytesMut,
+    /// Address of the first byte if the input format provides one.
+    pub base_address: Option<u64>,
+    /// Ascending ranges of `bytes` between the records of an IntelHex
+    /// input, they hold no data and are not written as records.
+    pub holes: Vec<Range<usize>>,
 }
 
 impl AnnotatedBytes {
     pub fn new() -> Self {
-        AnnotatedBytes {
-            bytes: BytesMut::new(),
-        }
+        Self::default()
     }
 
+    /// Load `path` of format `meta_in`, `-` reads from stdin.
+    pub fn load(path: &Path, meta_in: MetaInfo) -> Result<Self> {
+        Self::parse(&read_input(path)?, meta_in)
+    }
+
+    /// Save to `path`, `-` writes to stdout.
     pub fn save(self, path: &Path, meta_out: MetaInfo) -> Result<()> {
-        match meta_out {
-            MetaInfo::Bin => {
-                let mut file = OpenOptions::new()
-                    .truncate(true)
-                    .write(true)
-                    .create(true)
-                    .open(path)?;
+        write_output(path, &self.encode(meta_out)?)
+    }
 
-                file.write_all(&self.bytes)?;
-            }
+    /// Content of a file of format `meta_out`.
+    pub fn encode(self, meta_out: MetaInfo) -> Result<Bytes> {
+        match meta_out {
+            MetaInfo::Bin => Ok(self.bytes.freeze()),
             MetaInfo::IntelHex => {
-                write_bin_as_hex_to_file(path, self.bytes)?;
+                let base = self.base_address.unwrap_or(0);
+                let hex = convert_bin2hex(&self.bytes, base, &self.holes)?;
+                Ok(Bytes::from(hex.into_bytes()))
             }
         }
-
-        Ok(())
     }
 
-    pub fn load(path: &Path, meta_in: MetaInfo) -> Result<Self> {
+    /// Image of the `content` of a file of format `meta_in`.
+    pub fn parse(content: &[u8], meta_in: MetaInfo) -> Result<Self> {
         match meta_in {
-            MetaInfo::Bin => {
-                let mut file = OpenOptions::new().read(true).open(path)?;
-                let mut bytes = Vec::new();
-                file.read_to_end(&mut bytes)?;
-
+            MetaInfo::Bin => Ok(AnnotatedBytes {
+                bytes: BytesMut::from(content),
+                ..Default::default()
+            }),
+            MetaInfo::IntelHex => {
+                let content = std::str::from_utf8(content)
+                    .map_err(|e| err!(Format, "IntelHex is not valid text: {}", e))?;
+                let (bytes, base_address, holes) = convert_hex2bin(content)?;
                 Ok(AnnotatedBytes {
-                    bytes: BytesMut::from(&bytes[..]),
+                    bytes,
+                    base_address: Some(base_address),
+                    holes,
                 })
             }
-            MetaInfo::IntelHex => Ok(AnnotatedBytes {
-                bytes: convert_hex2bin(path)?,
-            }),
         }
     }
 
-    pub fn stance(&mut self, start: ByteOffset, size: ByteOffset) {
-        if start.as_usize() > 0usize && start.as_usize() < self.bytes.len() {
+    /// Address of the first byte, either the explicitly given `base`, the
+    /// base address of the input or the flash base of `device`.
+    pub fn resolve_base(
+        &self,
+        base: Option<u64>,
+        device: Option<&DeviceProfile>,
+    ) -> Result<Option<u64>> {
+        match (base, self.base_address, device) {
+            (Some(base), _, _) | (None, Some(base), _) => Ok(Some(base)),
+            (None, None, Some(device)) => Ok(Some(device.flash_base.as_u64()?)),
+            (None, None, None) => Ok(None),
+        }
+    }
+
+    /// Ascending address ranges holding data, addresses start at 0 without
+    /// a base address.
+    pub fn segments(&self) -> Result<Vec<Range<u64>>> {
+        let base = self.base_address.unwrap_or(0);
+        let address = |offset: usize| {
+            base.checked_add(offset as u64).ok_or_else(|| {
+                err!(
+                    OutOfBounds,
+                    "Address of {:#x} exceeds the 64 bit range",
+                    offset
+                )
+            })
+        };
+        let len = self.bytes.len();
+        let mut segments = Vec::new();
+        let mut pos = 0;
+        for hole in self.holes.iter().chain(std::iter::once(&(len..len))) {
+            if hole.start > pos {
+                segments.push(address(pos)?..address(hole.start)?);
+            }
+            pos = hole.end;
+        }
+        Ok(segments)
+    }
+
+    /// Track in `holes` that the bytes of `range` were replaced by `len`
+    /// bytes of data, shifting the holes behind.
+    fn splice_holes(&mut self, range: Range<usize>, len: usize) {
+        let shift = |offset: usize| offset - range.end + range.start + len;
+        let mut holes: Vec<Range<usize>> = Vec::with_capacity(self.holes.len() + 1);
+        for hole in std::mem::take(&mut self.holes) {
+            let before = hole.start..std::cmp::min(hole.end, range.start);
+            let after = std::cmp::max(hole.start, range.end)..hole.end;
+            let after = if after.is_empty() {
+                after
+            } else {
+                shift(after.start)..shift(after.end)
+            };
+            for part in [before, after] {
+                match holes.last_mut() {
+                    _ if part.is_empty() => {}
+                    Some(last) if last.end == part.start => last.end = part.end,
+                    _ => holes.push(part),
+                }
+            }
+        }
+        self.holes = holes;
+    }
+
+    /// Pad the end with `fill_pattern` up to the next sector boundary.
+    pub fn pad_to_sector(&mut self, sectors: &SectorMap, fill_pattern: &FillPattern) -> Result<()> {
+        let len = u64::try_from(self.bytes.len())?;
+        let end = usize::try_from(sectors.boundary_at_or_after(len)?)?;
+        fill_pattern.fill(&mut self.bytes, end)
+    }
+
+    pub fn stance(&mut self, start: ByteOffset, size: ByteOffset) -> Result<()> {
+        let start_idx = start.as_usize()?;
+        let size = size.as_usize()?;
+        let len = self.bytes.len();
+
+        if start_idx <= len {
             // split file in part before and after start index
-            self.bytes = self.bytes.split_off(start.as_usize() - 1);
+            self.bytes = self.bytes.split_off(start_idx);
+            // the remaining bytes keep their addresses
+            if let Some(base) = self.base_address.as_mut() {
+                *base = base.checked_add(start_idx as u64).ok_or_else(|| {
+                    err!(OutOfBounds, "Address of {} exceeds the 64 bit range", start)
+                })?;
+            }
         } else {
             warn!("start {} is outside file size {}", start, self.bytes.len());
+            self.bytes.clear();
+            self.holes.clear();
         }
+        self.splice_holes(0..start_idx.min(len), 0);
 
-        if size.as_usize() < self.bytes.len() {
+        if size < self.bytes.len() {
             // split off everything after size
-            self.bytes.truncate(size.as_usize());
+            self.splice_holes(size..self.bytes.len(), 0);
+            self.bytes.truncate(size);
+        }
+        Ok(())
+    }
+
+    /// Overwrite the bytes at `at` with `value`, the image size is unchanged.
+    pub fn patch(&mut self, at: &ByteOffset, value: &[u8]) -> Result<()> {
+        let start = at.as_usize()?;
+        let end = start
+            .checked_add(value.len())
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| {
+                err!(
+                    OutOfBounds,
+                    "Patch of {} bytes at {} exceeds the image size {}",
+                    value.len(),
+                    at,
+                    self.bytes.len()
+                )
+            })?;
+        self.bytes[start..end].copy_from_slice(value);
+        self.splice_holes(start..end, value.len());
+        Ok(())
+    }
+
+    /// Splice `data` in at `at`, shifting all subsequent bytes.
+    pub fn insert(&mut self, at: &ByteOffset, data: &[u8]) -> Result<()> {
+        let at_idx = at.as_usize()?;
+        if at_idx > self.bytes.len() {
+            return Err(err!(
+                OutOfBounds,
+                "Insert position {} is beyond the image size {}",
+                at,
+                self.bytes.len()
+            ));
+        }
+        let tail = self.bytes.split_off(at_idx);
+        self.bytes.extend_from_slice(data);
+        self.bytes.unsplit(tail);
+        self.splice_holes(at_idx..at_idx, data.len());
+        Ok(())
+    }
+
+    /// Cut `size` bytes at `start` out, shifting all subsequent bytes.
+    pub fn delete(&mut self, start: &ByteOffset, size: &ByteOffset) -> Result<()> {
+        let start_idx = start.as_usize()?;
+        let end_idx = start_idx
+            .checked_add(size.as_usize()?)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| {
+                err!(
+                    OutOfBounds,
+                    "Deleting {} bytes at {} exceeds the image size {}",
+                    size,
+                    start,
+                    self.bytes.len()
+                )
+            })?;
+        let tail = self.bytes.split_off(end_idx);
+        self.bytes.truncate(start_idx);
+        self.bytes.unsplit(tail);
+        self.splice_holes(start_idx..end_idx, 0);
+        Ok(())
+    }
+
+    /// Replace each of the ascending, non-overlapping `ranges` with `with`.
+    ///
+    /// Unless `resize` is set, every range must be as long as `with` so
+    /// the image size is unchanged.
+    pub fn replace(
+        &mut self,
+        ranges: &[std::ops::Range<usize>],
+        with: &[u8],
+        resize: bool,
+    ) -> Result<()> {
+        if let Some(range) = ranges.iter().find(|range| range.len() != with.len()) {
+            if !resize {
+                return Err(err!(
+                    OutOfBounds,
+                    "Match of {} bytes at {:#x} differs from the replacement of {} bytes",
+                    range.len(),
+                    range.start,
+                    with.len()
+                ));
+            }
+        }
+        let mut replaced = BytesMut::with_capacity(self.bytes.len());
+        let mut pos = 0;
+        for range in ranges {
+            replaced.extend_from_slice(&self.bytes[pos..range.start]);
+            replaced.extend_from_slice(with);
+            pos = range.end;
         }
+        replaced.extend_from_slice(&self.bytes[pos..]);
+        self.bytes = replaced;
+        // from the back, so the offsets of the preceding ranges are kept
+        for range in ranges.iter().rev() {
+            self.splice_holes(range.clone(), with.len());
+        }
+        Ok(())
+    }
+
+    /// Reverse the byte order within each word of `width` bytes of the
+    /// `size` bytes at `start`.
+    pub fn swap(&mut self, start: &ByteOffset, size: &ByteOffset, width: usize) -> Result<()> {
+        let start_idx = start.as_usize()?;
+        let size_idx = size.as_usize()?;
+        let end_idx = start_idx
+            .checked_add(size_idx)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| {
+                err!(
+                    OutOfBounds,
+                    "Swapping {} bytes at {} exceeds the image size {}",
+                    size,
+                    start,
+                    self.bytes.len()
+                )
+            })?;
+        swap_words(&mut self.bytes[start_idx..end_idx], width)?;
+        self.splice_holes(start_idx..end_idx, size_idx);
+        Ok(())
+    }
+
+    /// `swap` which first pads the image with `pad` to a multiple of
+    /// `width` if given and the range reaches the end of the image.
+    pub fn swap_padded(
+        &mut self,
+        start: &ByteOffset,
+        size: &ByteOffset,
+        width: usize,
+        pad: Option<&FillPattern>,
+    ) -> Result<()> {
+        let len = self.bytes.len() as u64;
+        let mut size = size.as_u64()?;
+        let end = start.as_u64()?.checked_add(size);
+        if let (Some(fill_pattern), Some(end)) = (pad, end) {
+            if end == len && width > 0 {
+                let padding = (width as u64 - size % width as u64) % width as u64;
+                size += padding;
+                self.resize(usize::try_from(len + padding)?, fill_pattern)?;
+            }
+        }
+        self.swap(start, &ByteOffset::from_u64(size), width)
+    }
+
+    /// Distribute the words of `width` bytes round robin onto `ways` lanes,
+    /// word `n` goes to lane `n % ways`.
+    ///
+    /// Each lane starts at the base address divided by `ways`, an image with
+    /// holes between its IntelHex records is rejected.
+    pub fn deinterleave(&self, ways: usize, width: usize) -> Result<Vec<AnnotatedBytes>> {
+        let stride = ways
+            .checked_mul(width)
+            .filter(|stride| *stride > 0)
+            .ok_or_else(|| err!(Parse, "Invalid {} ways of {} byte words", ways, width))?;
+        if let Some(hole) = self.holes.first() {
+            return Err(err!(
+                Format,
+                "Image with a hole at {:#x}..{:#x} can not be deinterleaved",
+                hole.start,
+                hole.end
+            ));
+        }
+        let base_address = match self.base_address {
+            Some(base) if base % stride as u64 != 0 => {
+                return Err(err!(
+                    Alignment,
+                    "Base address {:#x} is not a multiple of {} ways of {} byte words",
+                    base,
+                    ways,
+                    width
+                ));
+            }
+            base => base.map(|base| base / ways as u64),
+        };
+        if self.bytes.len() % stride != 0 {
+            return Err(err!(
+                Alignment,
+                "Image size {} is not a multiple of {} ways of {} byte words",
+                self.bytes.len(),
+                ways,
+                width
+            ));
+        }
+        let mut lanes = vec![
+            AnnotatedBytes {
+                base_address,
+                ..Default::default()
+            };
+            ways
+        ];
+        for (idx, word) in self.bytes.chunks(width).enumerate() {
+            lanes[idx % ways].bytes.extend_from_slice(word);
+        }
+        Ok(lanes)
+    }
+
+    /// Merge `lanes` taking a word of `width` bytes from each in turn, the
+    /// inverse of `deinterleave`.
+    ///
+    /// The result starts at the base address of the lanes multiplied by
+    /// their number, lanes with holes or different base addresses are
+    /// rejected.
+    pub fn interleave(lanes: &[AnnotatedBytes], width: usize) -> Result<AnnotatedBytes> {
+        let len = lanes.first().map(|lane| lane.bytes.len()).unwrap_or(0);
+        if width == 0 || len % width != 0 {
+            return Err(err!(
+                Alignment,
+                "Lane size {} is not a multiple of the word width {}",
+                len,
+                width
+            ));
+        }
+        if let Some(lane) = lanes.iter().find(|lane| lane.bytes.len() != len) {
+            return Err(err!(
+                Alignment,
+                "All lanes must have the same size, got {} and {}",
+                len,
+                lane.bytes.len()
+            ));
+        }
+        if let Some(lane) = lanes.iter().find(|lane| !lane.holes.is_empty()) {
+            return Err(err!(
+                Format,
+                "Lane with a hole at {:#x}..{:#x} can not be interleaved",
+                lane.holes[0].start,
+                lane.holes[0].end
+            ));
+        }
+        let base = lanes.first().and_then(|lane| lane.base_address);
+        if let Some(lane) = lanes.iter().find(|lane| lane.base_address != base) {
+            return Err(err!(
+                Format,
+                "All lanes must have the same base address, got {:?} and {:?}",
+                base,
+                lane.base_address
+            ));
+        }
+        let mut interleaved = AnnotatedBytes::new();
+        interleaved.base_address = base
+            .map(|base| {
+                base.checked_mul(lanes.len() as u64).ok_or_else(|| {
+                    err!(
+                        OutOfBounds,
+                        "Base address {:#x} of {} lanes exceeds the 64 bit range",
+                        base,
+                        lanes.len()
+                    )
+                })
+            })
+            .transpose()?;
+        for start in (0..len).step_by(width) {
+            for lane in lanes {
+                interleaved
+                    .bytes
+                    .extend_from_slice(&lane.bytes[start..start + width]);
+            }
+        }
+        Ok(interleaved)
+    }
+
+    /// Truncate or pad with `fill_pattern` to exactly `len` bytes.
+    pub fn resize(&mut self, len: usize, fill_pattern: &FillPattern) -> Result<()> {
+        if len < self.bytes.len() {
+            self.splice_holes(len..self.bytes.len(), 0);
+        }
+        self.bytes.truncate(len);
+        fill_pattern.fill(&mut self.bytes, len)
     }
 
     pub fn stitch(
@@ -139,23 +1120,22 @@ impl AnnotatedBytes {
         files
             .into_iter()
             .try_fold(AnnotatedBytes::new(), |mut stitched, (elem, offset)| {
+                let offset_idx = offset.as_usize()?;
                 // check if offset is greater than length
-                if stitched.bytes.len() > offset.as_usize() {
-                    return Err(format_err!(
+                if stitched.bytes.len() > offset_idx {
+                    return Err(err!(
+                        OutOfBounds,
                         "Offset {} smaller than current file {}",
                         offset,
                         stitched.bytes.len()
                     ));
                 }
-                match fill_pattern {
-                    FillPattern::Zero => stitched.bytes.resize(offset.as_usize(), 0x00),
-                    FillPattern::One => stitched.bytes.resize(offset.as_usize(), 0xFF),
-                    FillPattern::Random => {
-                        let mut padding = vec![0; offset.as_usize() - stitched.bytes.len()];
-                        ::rand::thread_rng().try_fill(&mut padding[..])?;
-                        stitched.bytes.extend_from_slice(&padding);
-                    }
-                }
+                fill_pattern.fill(&mut stitched.bytes, offset_idx)?;
+                stitched.holes.extend(
+                    elem.holes
+                        .iter()
+                        .map(|hole| hole.start + offset_idx..hole.end + offset_idx),
+                );
                 stitched.bytes.extend_from_slice(&elem.bytes);
                 Ok(stitched)
             })
@@ -167,39 +1147,50 @@ impl AnnotatedBytes {
         start: ByteOffset,
         size: ByteOffset,
         fill_pattern: FillPattern,
+        mode: GraftMode,
     ) -> Result<()> {
-        // [prefix replacement postfix]
+        // [prefix replacement padding postfix]
+        let start_idx = start.as_usize()?;
+        let size_idx = usize::try_from(mode.range_size(
+            self.bytes.len() as u64,
+            start.as_u64()?,
+            size.as_u64()?,
+        )?)?;
+        let end_idx = start_idx + size_idx;
+        let len = self.bytes.len();
 
-        let mut output = self.bytes.clone();
-        // split file in part before and after start index
-        let after = output.split_off(start.as_usize());
-
-        // get length of replacing part
-        let prefix_len = &output.len();
-
-        if replace.bytes.len() > size.as_usize() {
-            return Err(format_err!(
-                "Failed to graft bytes, size is smaller than replacing bytes"
-            ));
+        if end_idx > self.bytes.len() {
+            fill_pattern.fill(&mut self.bytes, end_idx)?;
         }
-        // append replacing bytes
-        output.extend_from_slice(&replace.bytes);
 
-        // fill missing bytes
-        match fill_pattern {
-            FillPattern::Zero => output.resize(prefix_len + size.as_usize(), 0x0),
-            FillPattern::One => output.resize(prefix_len + size.as_usize(), 0xFF),
-            FillPattern::Random => {
-                let mut padding = vec![0; size.as_usize() - replace.bytes.len()];
-                ::rand::thread_rng().try_fill(&mut padding[..])?;
-                output.extend_from_slice(&padding);
+        let mut replacement = &replace.bytes[..];
+        if replacement.len() > size_idx {
+            match mode {
+                GraftMode::Strict => {
+                    return Err(err!(
+                        OutOfBounds,
+                        "Failed to graft {} bytes, size {} is smaller than replacing bytes",
+                        replacement.len(),
+                        size
+                    ));
+                }
+                GraftMode::Truncate => replacement = &replacement[..size_idx],
+                // the tail is shifted back by the excess
+                GraftMode::Grow => {}
             }
         }
 
-        // append the end
-        output.extend_from_slice(&after[size.as_usize()..]);
+        let postfix = self.bytes.split_off(end_idx);
+        self.bytes.truncate(start_idx);
+        self.bytes.extend_from_slice(replacement);
+
+        // fill missing bytes
+        fill_pattern.fill(&mut self.bytes, end_idx)?;
 
-        self.bytes = output;
+        self.bytes.unsplit(postfix);
+        let grafted = start_idx.min(len)..end_idx.min(len);
+        let grafted_len = self.bytes.len() - (len - grafted.len());
+        self.splice_holes(grafted, grafted_len);
 
         Ok(())
     }
@@ -251,6 +1242,7 @@ mod test {
                 ByteOffset::new(10, Magnitude::Unit),
                 ByteOffset::new(size as u64, Magnitude::Unit),
                 FillPattern::One,
+                GraftMode::Strict,
             )
             .expect("Failed to graft");
 
@@ -263,6 +1255,85 @@ mod test {
         assert_eq!(in_bytes.bytes[10 + size..], ones[..]);
     }
 
+    fn graft_with(
+        in_len: usize,
+        graft_len: usize,
+        start: u64,
+        size: u64,
+        mode: GraftMode,
+    ) -> Result<Vec<u8>> {
+        let mut in_bytes = AnnotatedBytes::new();
+        let mut graft_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.resize(in_len, 1u8);
+        graft_bytes.bytes.resize(graft_len, 2u8);
+        in_bytes.graft(
+            graft_bytes,
+            ByteOffset::new(start, Magnitude::Unit),
+            ByteOffset::new(size, Magnitude::Unit),
+            FillPattern::One,
+            mode,
+        )?;
+        Ok(in_bytes.bytes.to_vec())
+    }
+
+    #[test]
+    fn graft_modes() {
+        // replacement exceeds the range
+        assert!(graft_with(10, 4, 2, 3, GraftMode::Strict).is_err());
+        assert_eq!(
+            graft_with(10, 4, 2, 3, GraftMode::Truncate).unwrap(),
+            vec![1, 1, 2, 2, 2, 1, 1, 1, 1, 1]
+        );
+        assert_eq!(
+            graft_with(10, 4, 2, 3, GraftMode::Grow).unwrap(),
+            vec![1, 1, 2, 2, 2, 2, 1, 1, 1, 1, 1]
+        );
+
+        // range exceeds the image
+        assert!(matches!(
+            graft_with(10, 2, 8, 4, GraftMode::Strict),
+            Err(Error::OutOfBounds(_))
+        ));
+        assert!(graft_with(10, 2, 12, 4, GraftMode::Truncate).is_err());
+        // truncate cuts the range at the end of the image
+        assert_eq!(
+            graft_with(10, 4, 8, 4, GraftMode::Truncate).unwrap(),
+            vec![1, 1, 1, 1, 1, 1, 1, 1, 2, 2]
+        );
+        assert_eq!(
+            graft_with(10, 1, 8, 4, GraftMode::Truncate).unwrap(),
+            vec![1, 1, 1, 1, 1, 1, 1, 1, 2, 0xFF]
+        );
+        assert_eq!(
+            graft_with(10, 2, 10, 4, GraftMode::Truncate).unwrap(),
+            vec![1; 10]
+        );
+        assert_eq!(
+            graft_with(10, 2, 8, 4, GraftMode::Grow).unwrap(),
+            vec![1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            graft_with(4, 2, 6, 3, GraftMode::Grow).unwrap(),
+            vec![1, 1, 1, 1, 0xFF, 0xFF, 2, 2, 0xFF]
+        );
+
+        // exactly up to the end
+        assert_eq!(
+            graft_with(4, 2, 2, 2, GraftMode::Strict).unwrap(),
+            vec![1, 1, 2, 2]
+        );
+    }
+
+    #[test]
+    fn graft_written() {
+        assert_eq!(GraftMode::Strict.written(10, 4, 2, 2).unwrap(), 4..6);
+        assert_eq!(GraftMode::Truncate.written(10, 4, 2, 3).unwrap(), 4..6);
+        assert_eq!(GraftMode::Truncate.written(10, 8, 4, 3).unwrap(), 8..10);
+        assert_eq!(GraftMode::Grow.written(10, 4, 2, 3).unwrap(), 4..7);
+        assert_eq!(GraftMode::Grow.written(10, 8, 4, 1).unwrap(), 8..12);
+        assert!(GraftMode::Strict.written(10, 8, 4, 1).is_err());
+    }
+
     #[test]
     fn stitch() {
         let bos: Vec<ByteOffset> = vec![
@@ -281,16 +1352,15 @@ mod test {
         byts[1].bytes.resize(4, 2u8);
         byts[2].bytes.resize(100, 3u8);
 
-        let stitch_vec: Vec<(AnnotatedBytes, ByteOffset)> =
-            byts.into_iter().zip(bos.into_iter()).collect();
+        let stitch_vec: Vec<(AnnotatedBytes, ByteOffset)> = byts.into_iter().zip(bos).collect();
 
         let stitched =
             AnnotatedBytes::stitch(stitch_vec, FillPattern::One).expect("Failed to stitch");
 
-        let ones = vec![1u8; 100];
-        let twos = vec![2u8; 4];
-        let threes = vec![3u8; 100];
-        let ffs = vec![255u8; 1000];
+        let ones = [1u8; 100];
+        let twos = [2u8; 4];
+        let threes = [3u8; 100];
+        let ffs = [255u8; 1000];
         assert_eq!(stitched.bytes[..30], ones[..30]);
         assert_eq!(stitched.bytes[30..1000], ffs[30..1000]);
         assert_eq!(stitched.bytes[1000..1004], twos[..]);
@@ -298,6 +1368,484 @@ mod test {
         assert_eq!(stitched.bytes[1024..], threes[..]);
     }
 
+    #[test]
+    fn fill_pattern_from_str() {
+        assert_eq!(FillPattern::from_str("One").unwrap(), FillPattern::One);
+        assert_eq!(
+            FillPattern::from_str("byte:0xA5").unwrap(),
+            FillPattern::Byte(0xA5)
+        );
+        assert_eq!(
+            FillPattern::from_str("byte:17").unwrap(),
+            FillPattern::Byte(17)
+        );
+        assert_eq!(
+            FillPattern::from_str("pattern:DEADBEEF").unwrap(),
+            FillPattern::Sequence(vec![0xDE, 0xAD, 0xBE, 0xEF])
+        );
+        assert_eq!(
+            FillPattern::from_str("address:u16be").unwrap(),
+            FillPattern::Address {
+                width: 2,
+                endianness: Endianness::Big
+            }
+        );
+
+        let bad = [
+            "",
+            "two",
+            "byte",
+            "byte:0x100",
+            "pattern:",
+            "pattern:ABC",
+            "pattern:XY",
+            "pattern:aéb",
+            "pattern:éé",
+            "pattern:+F",
+            "file:NonExisitingFileName",
+            "address:u24le",
+        ];
+        bad.iter().for_each(|fill_pattern| {
+            assert!(
+                FillPattern::from_str(fill_pattern).is_err(),
+                "{} should not parse",
+                fill_pattern
+            );
+        });
+    }
+
+    #[test]
+    fn fill_pattern_fill() {
+        let mut bytes = BytesMut::from(&[1u8, 2, 3][..]);
+        FillPattern::Sequence(vec![0xDE, 0xAD, 0xBE, 0xEF])
+            .fill(&mut bytes, 9)
+            .unwrap();
+        assert_eq!(bytes[..], [1, 2, 3, 0xEF, 0xDE, 0xAD, 0xBE, 0xEF, 0xDE]);
+
+        let mut bytes = BytesMut::from(&[1u8, 2][..]);
+        FillPattern::Byte(0xA5).fill(&mut bytes, 4).unwrap();
+        assert_eq!(bytes[..], [1, 2, 0xA5, 0xA5]);
+
+        let mut bytes = BytesMut::from(&[1u8][..]);
+        FillPattern::Address {
+            width: 4,
+            endianness: Endianness::Little,
+        }
+        .fill(&mut bytes, 12)
+        .unwrap();
+        assert_eq!(bytes[..], [1, 0, 0, 0, 4, 0, 0, 0, 8, 0, 0, 0]);
+
+        let mut bytes = BytesMut::new();
+        FillPattern::Address {
+            width: 2,
+            endianness: Endianness::Big,
+        }
+        .fill(&mut bytes, 6)
+        .unwrap();
+        assert_eq!(bytes[..], [0, 0, 0, 2, 0, 4]);
+
+        // never shrinks
+        FillPattern::Zero.fill(&mut bytes, 2).unwrap();
+        assert_eq!(bytes.len(), 6);
+    }
+
+    #[test]
+    fn seeded_random() {
+        assert_eq!(
+            FillPattern::from_str("random:0x2a").unwrap(),
+            FillPattern::SeededRandom(42)
+        );
+
+        let mut whole = BytesMut::new();
+        FillPattern::SeededRandom(42).fill(&mut whole, 100).unwrap();
+
+        // padding only depends on the offset, not where it started
+        let mut split = BytesMut::new();
+        split.resize(13, 0u8);
+        FillPattern::SeededRandom(42).fill(&mut split, 100).unwrap();
+        assert_eq!(whole[13..], split[13..]);
+
+        let mut other = BytesMut::new();
+        FillPattern::SeededRandom(43).fill(&mut other, 100).unwrap();
+        assert_ne!(whole, other);
+
+        // pin the stream, images must stay bit-identical across releases
+        assert_eq!(whole[..8], [0x78, 0x48, 0xb5, 0xd7, 0x11, 0xbc, 0x98, 0x83]);
+    }
+
+    #[test]
+    fn content_seed_stable() {
+        assert_eq!(content_seed(vec![]), 0xcbf2_9ce4_8422_2325);
+        let a: &[u8] = b"ab";
+        let b: &[u8] = b"c";
+        let c: &[u8] = b"a";
+        let d: &[u8] = b"bc";
+        assert_ne!(content_seed(vec![a, b]), content_seed(vec![c, d]));
+        assert_eq!(content_seed(vec![a, b]), content_seed(vec![a, b]));
+    }
+
+    #[test]
+    fn pad_to_sector() {
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.resize(0x4100, 2u8);
+
+        let sectors = "16Ki:4,64Ki".parse::<SectorMap>().unwrap();
+        in_bytes
+            .pad_to_sector(&sectors, &FillPattern::One)
+            .expect("Failed to pad");
+        assert_eq!(in_bytes.bytes.len(), 0x8000);
+        assert_eq!(in_bytes.bytes[0x40FF], 2u8);
+        assert_eq!(in_bytes.bytes[0x4100..], [0xFFu8; 0x3F00][..]);
+
+        in_bytes
+            .pad_to_sector(&sectors, &FillPattern::One)
+            .expect("Failed to pad");
+        assert_eq!(in_bytes.bytes.len(), 0x8000);
+    }
+
+    #[test]
+    fn patch() {
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.resize(16, 0u8);
+
+        in_bytes
+            .patch(&ByteOffset::new(12, Magnitude::Unit), &[1, 2, 3, 4])
+            .expect("Failed to patch");
+        assert_eq!(in_bytes.bytes.len(), 16);
+        assert_eq!(in_bytes.bytes[11..], [0, 1, 2, 3, 4]);
+
+        assert!(in_bytes
+            .patch(&ByteOffset::new(13, Magnitude::Unit), &[1, 2, 3, 4])
+            .is_err());
+        assert!(in_bytes
+            .patch(&ByteOffset::new(u64::MAX, Magnitude::Unit), &[1])
+            .is_err());
+    }
+
+    #[test]
+    fn insert_delete() {
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.extend_from_slice(&[0, 1, 2, 3, 4, 5]);
+
+        in_bytes
+            .insert(&ByteOffset::new(2, Magnitude::Unit), &[9, 9])
+            .expect("Failed to insert");
+        assert_eq!(in_bytes.bytes[..], [0, 1, 9, 9, 2, 3, 4, 5]);
+        in_bytes
+            .insert(&ByteOffset::new(8, Magnitude::Unit), &[7])
+            .expect("Failed to insert");
+        assert_eq!(in_bytes.bytes[..], [0, 1, 9, 9, 2, 3, 4, 5, 7]);
+        assert!(in_bytes
+            .insert(&ByteOffset::new(10, Magnitude::Unit), &[7])
+            .is_err());
+
+        in_bytes
+            .delete(
+                &ByteOffset::new(1, Magnitude::Unit),
+                &ByteOffset::new(3, Magnitude::Unit),
+            )
+            .expect("Failed to delete");
+        assert_eq!(in_bytes.bytes[..], [0, 2, 3, 4, 5, 7]);
+        assert!(in_bytes
+            .delete(
+                &ByteOffset::new(4, Magnitude::Unit),
+                &ByteOffset::new(3, Magnitude::Unit),
+            )
+            .is_err());
+
+        in_bytes.resize(8, &FillPattern::One).unwrap();
+        assert_eq!(in_bytes.bytes[..], [0, 2, 3, 4, 5, 7, 0xFF, 0xFF]);
+        in_bytes.resize(3, &FillPattern::One).unwrap();
+        assert_eq!(in_bytes.bytes[..], [0, 2, 3]);
+    }
+
+    #[test]
+    fn replace() {
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.extend_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+
+        in_bytes
+            .replace(&[1..3, 5..7], &[9, 9], false)
+            .expect("Failed to replace");
+        assert_eq!(in_bytes.bytes[..], [0, 9, 9, 3, 4, 9, 9, 7]);
+
+        assert!(in_bytes.replace(&[0..1, 4..5], &[8, 8], false).is_err());
+        in_bytes
+            .replace(&[0..1, 3..5], &[8, 8], true)
+            .expect("Failed to replace");
+        assert_eq!(in_bytes.bytes[..], [8, 8, 9, 9, 8, 8, 9, 9, 7]);
+    }
+
+    #[test]
+    fn swap() {
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes
+            .bytes
+            .extend_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        in_bytes
+            .swap(&ByteOffset::from_u64(0), &ByteOffset::from_u64(8), 4)
+            .expect("Failed to swap");
+        assert_eq!(in_bytes.bytes[..], [3, 2, 1, 0, 7, 6, 5, 4, 8, 9]);
+        in_bytes
+            .swap(&ByteOffset::from_u64(8), &ByteOffset::from_u64(2), 2)
+            .expect("Failed to swap");
+        assert_eq!(in_bytes.bytes[8..], [9, 8]);
+
+        assert!(in_bytes
+            .swap(&ByteOffset::from_u64(0), &ByteOffset::from_u64(10), 4)
+            .is_err());
+        assert!(in_bytes
+            .swap(&ByteOffset::from_u64(4), &ByteOffset::from_u64(8), 2)
+            .is_err());
+        assert!(in_bytes
+            .swap(&ByteOffset::from_u64(0), &ByteOffset::from_u64(8), 0)
+            .is_err());
+        assert!(matches!(
+            in_bytes.swap(&ByteOffset::from_u64(0), &ByteOffset::from_u64(9), 3),
+            Err(Error::Parse(_))
+        ));
+
+        // padded only if the range reaches the end
+        let (start, size) = (ByteOffset::from_u64(4), ByteOffset::from_u64(6));
+        in_bytes
+            .swap_padded(&start, &size, 4, Some(&FillPattern::Zero))
+            .expect("Failed to swap");
+        assert_eq!(in_bytes.bytes[4..], [4, 5, 6, 7, 0, 0, 8, 9]);
+        assert!(in_bytes
+            .swap_padded(&ByteOffset::from_u64(0), &size, 4, Some(&FillPattern::Zero))
+            .is_err());
+    }
+
+    #[test]
+    fn interleave() {
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes
+            .bytes
+            .extend_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+
+        let lanes = in_bytes.deinterleave(2, 1).expect("Failed to deinterleave");
+        assert_eq!(lanes[0].bytes[..], [0, 2, 4, 6, 8, 10]);
+        assert_eq!(lanes[1].bytes[..], [1, 3, 5, 7, 9, 11]);
+
+        let lanes = in_bytes.deinterleave(3, 2).expect("Failed to deinterleave");
+        assert_eq!(lanes[0].bytes[..], [0, 1, 6, 7]);
+        assert_eq!(lanes[2].bytes[..], [4, 5, 10, 11]);
+        let merged = AnnotatedBytes::interleave(&lanes, 2).expect("Failed to interleave");
+        assert_eq!(merged.bytes, in_bytes.bytes);
+
+        assert!(in_bytes.deinterleave(5, 1).is_err());
+        assert!(in_bytes.deinterleave(0, 1).is_err());
+        assert!(AnnotatedBytes::interleave(&lanes[..], 3).is_err());
+        assert!(AnnotatedBytes::interleave(&[lanes[0].clone(), in_bytes], 2).is_err());
+    }
+
+    #[test]
+    fn interleave_hex() {
+        // 4 bytes at 0x0800_0000
+        let content = ":020000040800F2\n:0400000000010203F6\n:00000001FF";
+        let in_bytes = AnnotatedBytes::parse(content.as_bytes(), MetaInfo::IntelHex).unwrap();
+
+        let lanes = in_bytes.deinterleave(2, 1).expect("Failed to deinterleave");
+        assert_eq!(lanes[0].base_address, Some(0x0400_0000));
+        assert_eq!(lanes[1].base_address, Some(0x0400_0000));
+        assert_eq!(
+            &lanes[1].clone().encode(MetaInfo::IntelHex).unwrap()[..],
+            &b":020000040400F6\n:020000000103FA\n:00000001FF"[..]
+        );
+
+        let merged = AnnotatedBytes::interleave(&lanes, 1).expect("Failed to interleave");
+        assert_eq!(merged.base_address, Some(0x0800_0000));
+        assert_eq!(
+            &merged.encode(MetaInfo::IntelHex).unwrap()[..],
+            content.as_bytes()
+        );
+
+        // the base must be a multiple of the stride
+        let mut unaligned = in_bytes.clone();
+        unaligned.base_address = Some(0x0800_0002);
+        assert!(matches!(
+            unaligned.deinterleave(4, 1),
+            Err(Error::Alignment(_))
+        ));
+
+        // lanes at different addresses
+        let mut moved = lanes.clone();
+        moved[1].base_address = None;
+        assert!(matches!(
+            AnnotatedBytes::interleave(&moved, 1),
+            Err(Error::Format(_))
+        ));
+
+        // holes can not be distributed onto the lanes
+        let content = ":020000040800F2\n:020000000002FC\n:02000400AABB95\n:00000001FF";
+        let sparse = AnnotatedBytes::parse(content.as_bytes(), MetaInfo::IntelHex).unwrap();
+        assert_eq!(sparse.holes, vec![2..4]);
+        assert!(matches!(sparse.deinterleave(2, 1), Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn read_missing_input() {
+        assert!(read_input(Path::new("NonExisitingFileName")).is_err());
+    }
+
+    #[test]
+    fn atomic_writer() {
+        let dir = std::env::temp_dir().join(format!("scalpel-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.bin");
+        std::fs::write(&path, b"old").unwrap();
+
+        let mut writer = AtomicWriter::create(&path).unwrap();
+        writer.write_all(b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        drop(writer);
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+
+        write_output(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        // no temporary file is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detect() {
+        let path = Path::new("image");
+        let detect = |header: &[u8]| MetaInfo::detect(path, header);
+
+        assert_eq!(
+            MetaInfo::detect(Path::new("fw.hex"), b"\x00").unwrap(),
+            (MetaInfo::IntelHex, Detection::Extension("hex".to_owned()))
+        );
+        assert_eq!(
+            detect(b"\r\n:0400100001020304E2\r\n:00000001FF\r\n").unwrap(),
+            (MetaInfo::IntelHex, Detection::Signature("IntelHex records"))
+        );
+        // text, but no valid record
+        assert_eq!(
+            detect(b":0400100001020304E3\n").unwrap(),
+            (MetaInfo::Bin, Detection::NoSignature)
+        );
+        assert_eq!(
+            detect(b"Hello, scalpel!\n").unwrap(),
+            (MetaInfo::Bin, Detection::NoSignature)
+        );
+        assert_eq!(
+            detect(b"\x00\xFF\n:00000001FF").unwrap(),
+            (MetaInfo::Bin, Detection::NoSignature)
+        );
+        assert_eq!(
+            detect(b"").unwrap(),
+            (MetaInfo::Bin, Detection::NoSignature)
+        );
+
+        assert!(detect(b"\x7fELF\x01\x01\x01").is_err());
+        assert!(detect(b"UF2\n\x57\x51\x5d\x9e\x00\x20").is_err());
+        assert!(detect(b"S00F000068656C6C6F202020202000003C\nS9030000FC\n").is_err());
+        assert!(detect(b"S30800000000010203F1\n").is_err());
+
+        // arbitrary content is never mistaken for records
+        for header in [
+            "S1a\u{e9}b\n",
+            "S\u{e9}\n",
+            "S1\u{e9}\u{e9}\n",
+            ":a\u{e9}b\n",
+            ":\u{e9}\u{e9}\u{e9}\u{e9}\n",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                detect(header.as_bytes()).unwrap(),
+                (MetaInfo::Bin, Detection::NoSignature),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn parse_formats() {
+        let hex = b":0400100001020304E2\n:00000001FF\n";
+        let in_bytes = AnnotatedBytes::parse(hex, MetaInfo::IntelHex).expect("Failed to parse");
+        assert_eq!(in_bytes.bytes[..], [1, 2, 3, 4]);
+        assert_eq!(in_bytes.base_address, Some(0x10));
+
+        let in_bytes = AnnotatedBytes::parse(hex, MetaInfo::Bin).expect("Failed to parse");
+        assert_eq!(in_bytes.bytes.len(), hex.len());
+        assert_eq!(in_bytes.base_address, None);
+
+        assert!(AnnotatedBytes::parse(b"\xFF\xFE", MetaInfo::IntelHex).is_err());
+
+        assert_eq!(MetaInfo::from_str("hex").unwrap(), MetaInfo::IntelHex);
+        assert_eq!(MetaInfo::from_str("IntelHex").unwrap(), MetaInfo::IntelHex);
+        assert_eq!(MetaInfo::from_str("bin").unwrap(), MetaInfo::Bin);
+        assert!(MetaInfo::from_str("elf").is_err());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let hex = b":020000040800F2\n:0400100001020304E2\n:00000001FF\n";
+        let mut in_bytes = AnnotatedBytes::parse(hex, MetaInfo::IntelHex).expect("Failed to parse");
+        assert_eq!(in_bytes.base_address, Some(0x0800_0010));
+        in_bytes
+            .patch(&ByteOffset::from_u64(0), &[0xAA])
+            .expect("Failed to patch");
+
+        let content = in_bytes
+            .encode(MetaInfo::IntelHex)
+            .expect("Failed to encode");
+        assert!(content.starts_with(b":020000040800F2\n:04001000AA"));
+        let out_bytes =
+            AnnotatedBytes::parse(&content, MetaInfo::IntelHex).expect("Failed to parse");
+        assert_eq!(out_bytes.base_address, Some(0x0800_0010));
+        assert_eq!(out_bytes.bytes[..], [0xAA, 2, 3, 4]);
+
+        let mut out_bytes = out_bytes;
+        out_bytes
+            .stance(ByteOffset::from_u64(2), ByteOffset::from_u64(2))
+            .expect("Failed to stance");
+        assert_eq!(out_bytes.base_address, Some(0x0800_0012));
+        assert_eq!(out_bytes.bytes[..], [3, 4]);
+    }
+
+    #[test]
+    fn sparse_holes() {
+        // 4 bytes at 0x0800_0000 and 0x0800_0100 each
+        let hex = b":020000040800F2\n:0400000001020304F2\n:04010000AABBCCDDED\n:00000001FF";
+        let mut in_bytes = AnnotatedBytes::parse(hex, MetaInfo::IntelHex).expect("Failed to parse");
+        assert_eq!(in_bytes.holes, vec![4..0x100]);
+        assert_eq!(
+            in_bytes.segments().unwrap(),
+            vec![0x0800_0000..0x0800_0004, 0x0800_0100..0x0800_0104]
+        );
+        assert_eq!(
+            &in_bytes.clone().encode(MetaInfo::IntelHex).unwrap()[..],
+            &hex[..]
+        );
+
+        // patched bytes hold data, inserted and deleted bytes shift the hole
+        in_bytes
+            .patch(&ByteOffset::from_u64(0x10), &[0x55; 2])
+            .expect("Failed to patch");
+        assert_eq!(in_bytes.holes, vec![4..0x10, 0x12..0x100]);
+        in_bytes
+            .insert(&ByteOffset::from_u64(2), &[0x66; 4])
+            .expect("Failed to insert");
+        assert_eq!(in_bytes.holes, vec![8..0x14, 0x16..0x104]);
+        in_bytes
+            .delete(&ByteOffset::from_u64(0x10), &ByteOffset::from_u64(0x10))
+            .expect("Failed to delete");
+        // adjacent holes are merged
+        assert_eq!(in_bytes.holes, vec![8..0xF4]);
+        in_bytes
+            .stance(ByteOffset::from_u64(0x20), ByteOffset::from_u64(0x100))
+            .expect("Failed to stance");
+        assert_eq!(in_bytes.holes, vec![0..0xD4]);
+        assert_eq!(in_bytes.base_address, Some(0x0800_0020));
+        assert_eq!(in_bytes.segments().unwrap(), vec![0x0800_00F4..0x0800_00F8]);
+    }
+
     #[test]
     fn stance() {
         let mut in_bytes = AnnotatedBytes::new();
@@ -305,12 +1853,56 @@ mod test {
 
         let start = ByteOffset::new(10, Magnitude::Unit);
         let size = ByteOffset::new(40, Magnitude::Unit);
-        in_bytes.stance(start.clone(), size.clone());
-        assert_eq!(in_bytes.bytes.len(), size.as_usize());
+        in_bytes
+            .stance(start.clone(), size.clone())
+            .expect("Failed to stance");
+        assert_eq!(in_bytes.bytes.len(), size.as_usize().unwrap());
 
         in_bytes.bytes.resize(100, 2u8);
         let size = ByteOffset::new(1, Magnitude::K);
-        in_bytes.stance(start.clone(), size);
-        assert_eq!(in_bytes.bytes.len(), 101 - start.as_usize());
+        in_bytes
+            .stance(start.clone(), size)
+            .expect("Failed to stance");
+        assert_eq!(in_bytes.bytes.len(), 100 - start.as_usize().unwrap());
+
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.extend_from_slice(&[0, 1, 2, 3, 4, 5]);
+        in_bytes
+            .stance(ByteOffset::from_u64(2), ByteOffset::from_u64(3))
+            .expect("Failed to stance");
+        assert_eq!(in_bytes.bytes[..], [2, 3, 4]);
+        in_bytes
+            .stance(ByteOffset::from_u64(0), ByteOffset::from_u64(2))
+            .expect("Failed to stance");
+        assert_eq!(in_bytes.bytes[..], [2, 3]);
+    }
+
+    #[test]
+    fn stance_overflow() {
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.resize(100, 2u8);
+
+        let start = ByteOffset::new(10, Magnitude::Unit);
+        let size = ByteOffset::new(u64::MAX, Magnitude::Ki);
+        assert!(in_bytes.stance(start, size).is_err());
+    }
+
+    #[test]
+    fn error_kinds() {
+        assert!(matches!(MetaInfo::from_str("elf"), Err(Error::Parse(_))));
+        assert!(matches!(
+            AnnotatedBytes::parse(b":0100000000FE\n", MetaInfo::IntelHex),
+            Err(Error::Format(_))
+        ));
+        assert!(matches!(
+            AnnotatedBytes::load(Path::new("does/not/exist.bin"), MetaInfo::Bin),
+            Err(Error::Io(_, _))
+        ));
+        let mut in_bytes = AnnotatedBytes::new();
+        in_bytes.bytes.resize(5, 0);
+        assert!(matches!(
+            in_bytes.deinterleave(2, 2),
+            Err(Error::Alignment(_))
+        ));
     }
 }
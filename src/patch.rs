@@ -0,0 +1,249 @@
+use serde::de;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::ops::{parse_hex_bytes, Endianness};
+
+/// A literal value to be written into an image, parsed from
+/// `<type>:<value>`.
+///
+/// * `hex:DEADBEEF` raw bytes
+/// * `file:key.der` content of a file
+/// * `str:v1.2.3` UTF-8 string, `strz:v1.2.3` NUL terminated,
+///   `str/16:v1.2.3` or `strz/16:v1.2.3` padded with NUL to 16 bytes
+/// * `u8`, `i8`, `u16le`, `u16be`, `i16le`, `i16be`, `u32le`, `u32be`,
+///   `i32le`, `i32be`, `u64le`, `u64be`, `i64le`, `i64be`, `f32le`, `f32be`,
+///   `f64le`, `f64be` typed numbers, integers in decimal or `0x` hex
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchValue {
+    pub bytes: Vec<u8>,
+}
+
+impl PatchValue {
+    fn string(modifier: &str, text: &str) -> Result<Vec<u8>> {
+        let (terminated, pad) = match modifier.find('/') {
+            Some(idx) => (&modifier[..idx], Some(&modifier[idx + 1..])),
+            None => (modifier, None),
+        };
+        let mut bytes = text.as_bytes().to_vec();
+        match terminated {
+            "str" => {}
+            "strz" => bytes.push(0u8),
+            _ => return Err(err!(Parse, "Unknown string type {}", modifier)),
+        }
+        if let Some(pad) = pad {
+            let pad = pad
+                .parse::<usize>()
+                .map_err(|e| err!(Parse, "Failed to parse padding of {}: {}", modifier, e))?;
+            if bytes.len() > pad {
+                return Err(err!(
+                    OutOfBounds,
+                    "String {} needs {} bytes, more than the padded size {}",
+                    text,
+                    bytes.len(),
+                    pad
+                ));
+            }
+            bytes.resize(pad, 0u8);
+        }
+        Ok(bytes)
+    }
+
+    fn number(kind: &str, value: &str) -> Result<Vec<u8>> {
+        let (kind, endianness) = if let Some(kind) = kind.strip_suffix("le") {
+            (kind, Some(Endianness::Little))
+        } else if let Some(kind) = kind.strip_suffix("be") {
+            (kind, Some(Endianness::Big))
+        } else {
+            (kind, None)
+        };
+        // little endian bytes of the value, the slice is cut to the width
+        let (le_bytes, width): ([u8; 8], usize) = match kind {
+            "u8" | "u16" | "u32" | "u64" => {
+                let num = parse_unsigned(value)?;
+                let width = kind[1..].parse::<usize>().expect("Matched above. qed") / 8;
+                if width < 8 && num >> (width * 8) != 0 {
+                    return Err(err!(OutOfBounds, "{} does not fit into {}", value, kind));
+                }
+                (num.to_le_bytes(), width)
+            }
+            "i8" | "i16" | "i32" | "i64" => {
+                let num = parse_signed(value)?;
+                let width = kind[1..].parse::<usize>().expect("Matched above. qed") / 8;
+                let bits = width as u32 * 8;
+                if bits < 64 && (num < -(1i64 << (bits - 1)) || num >= 1i64 << (bits - 1)) {
+                    return Err(err!(OutOfBounds, "{} does not fit into {}", value, kind));
+                }
+                (num.to_le_bytes(), width)
+            }
+            "f32" => {
+                let num = value
+                    .parse::<f32>()
+                    .map_err(|e| err!(Parse, "Failed to parse {} as f32: {}", value, e))?;
+                let mut le_bytes = [0u8; 8];
+                le_bytes[..4].copy_from_slice(&num.to_le_bytes());
+                (le_bytes, 4)
+            }
+            "f64" => {
+                let num = value
+                    .parse::<f64>()
+                    .map_err(|e| err!(Parse, "Failed to parse {} as f64: {}", value, e))?;
+                (num.to_le_bytes(), 8)
+            }
+            _ => return Err(err!(Parse, "Unknown value type {}", kind)),
+        };
+        let mut bytes = le_bytes[..width].to_vec();
+        match (width, endianness) {
+            (1, None) => {}
+            (1, Some(_)) => return Err(err!(Parse, "Single bytes have no endianness")),
+            (_, None) => return Err(err!(Parse, "Missing endianness le or be for {}", kind)),
+            (_, Some(Endianness::Little)) => {}
+            (_, Some(Endianness::Big)) => bytes.reverse(),
+        }
+        Ok(bytes)
+    }
+}
+
+fn parse_unsigned(value: &str) -> Result<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    }
+    .map_err(|e| err!(Parse, "Failed to parse {}: {}", value, e))
+}
+
+fn parse_signed(value: &str) -> Result<i64> {
+    match value.strip_prefix('-') {
+        Some(abs) => {
+            let abs = parse_unsigned(abs)?;
+            0i64.checked_sub_unsigned(abs)
+                .ok_or_else(|| err!(OutOfBounds, "{} does not fit into i64", value))
+        }
+        None => {
+            let num = parse_unsigned(value)?;
+            i64::try_from(num).map_err(|_| err!(OutOfBounds, "{} does not fit into i64", value))
+        }
+    }
+}
+
+impl FromStr for PatchValue {
+    type Err = Error;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let idx = s
+            .find(':')
+            .ok_or_else(|| err!(Parse, "Missing type in {}, expected <type>:<value>", s))?;
+        let (kind, value) = (&s[..idx], &s[idx + 1..]);
+        let bytes = match kind {
+            "hex" => parse_hex_bytes(value)?,
+            "file" => {
+                fs::read(value).map_err(|e| Error::Io(format!("Failed to open {}", value), e))?
+            }
+            kind if kind.starts_with("str") => Self::string(kind, value)?,
+            kind => Self::number(kind, value)?,
+        };
+        if bytes.is_empty() {
+            return Err(err!(Parse, "Patch value {} is empty", s));
+        }
+        Ok(PatchValue { bytes })
+    }
+}
+
+impl<'de> de::Deserialize<'de> for PatchValue {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct PatchValueVisitor;
+
+        impl<'de> de::Visitor<'de> for PatchValueVisitor {
+            type Value = PatchValue;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expected a PatchValue")
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::std::result::Result<PatchValue, E>
+            where
+                E: de::Error,
+            {
+                PatchValue::from_str(value).map_err(|e| E::custom(e.to_string()))
+            }
+        }
+        deserializer.deserialize_str(PatchValueVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bytes(s: &str) -> Vec<u8> {
+        PatchValue::from_str(s).expect("Failed to parse").bytes
+    }
+
+    #[test]
+    fn raw_and_strings() {
+        assert_eq!(bytes("hex:DEADBEEF"), vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(bytes("str:v1.2"), b"v1.2".to_vec());
+        assert_eq!(bytes("strz:v1.2"), b"v1.2\0".to_vec());
+        assert_eq!(bytes("str/6:v1.2"), b"v1.2\0\0".to_vec());
+        assert_eq!(bytes("strz/5:v1.2"), b"v1.2\0".to_vec());
+        assert_eq!(bytes("str:a:b"), b"a:b".to_vec());
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(bytes("u8:0xA5"), vec![0xA5]);
+        assert_eq!(bytes("u16le:0x1234"), vec![0x34, 0x12]);
+        assert_eq!(bytes("u16be:0x1234"), vec![0x12, 0x34]);
+        assert_eq!(bytes("u32be:42"), vec![0, 0, 0, 42]);
+        assert_eq!(bytes("i16le:-2"), vec![0xFE, 0xFF]);
+        assert_eq!(bytes("i8:-128"), vec![0x80]);
+        assert_eq!(bytes("u64le:1"), vec![1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bytes("f32le:1.5"), vec![0x00, 0x00, 0xC0, 0x3F]);
+        assert_eq!(bytes("f32be:1.5"), vec![0x3F, 0xC0, 0x00, 0x00]);
+        assert_eq!(
+            bytes("f64be:-2"),
+            vec![0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn bad_values() {
+        let bad = [
+            "DEADBEEF",
+            "hex:",
+            "hex:ABC",
+            "hex:aéb",
+            "hex:éé",
+            "hex:0xA",
+            "str:",
+            "str/2:abc",
+            "strx:abc",
+            "u8:256",
+            "u8be:1",
+            "u16:1",
+            "u16le:0x10000",
+            "i8:128",
+            "i8:-129",
+            "u32le:-1",
+            "u24le:1",
+            "f32le:one",
+            "file:NonExisitingFileName",
+        ];
+        bad.iter().for_each(|value| {
+            assert!(
+                PatchValue::from_str(value).is_err(),
+                "{} should not parse",
+                value
+            );
+        });
+    }
+}
@@ -1,10 +1,10 @@
-use failure::{format_err, Error};
-use lazy_static::lazy_static;
-use regex::{Captures, Regex};
 use serde::de;
 use std::fmt;
+use std::str::FromStr;
 
 use crate::byte_offset::ByteOffset;
+use crate::error::{Error, Result};
+use crate::expression::split_top_level;
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct Range {
@@ -16,6 +16,52 @@ impl Range {
     pub fn new(start: ByteOffset, size: ByteOffset) -> Self {
         Range { start, size }
     }
+
+    pub fn from_u64(start: u64, size: u64) -> Self {
+        Range::new(ByteOffset::from_u64(start), ByteOffset::from_u64(size))
+    }
+
+    /// First offset past the range, errors if it is not representable.
+    pub fn end(&self) -> Result<ByteOffset> {
+        self.start.checked_add(&self.size)
+    }
+}
+
+impl FromStr for Range {
+    type Err = Error;
+
+    /// Parses `<start>..<end>` or `<start>+<size>`, where each side is a
+    /// byte offset expression. The first `+` outside of parentheses
+    /// separates start and size, so arithmetic in the start must be
+    /// enclosed in parentheses, i.e. `(16Ki*3)+4Ki`.
+    fn from_str(value: &str) -> ::std::result::Result<Self, Self::Err> {
+        let range = if let Some((start_str, end_str)) = split_top_level(value, "..") {
+            let start = ByteOffset::from_str(start_str)
+                .map_err(|e| err!(Parse, "Failed to parse start {}", e))?;
+            let end = ByteOffset::from_str(end_str)
+                .map_err(|e| err!(Parse, "Failed to parse end {}", e))?;
+            if start > end {
+                return Err(err!(
+                    Parse,
+                    "Start {} must not be greater than end {}",
+                    &start,
+                    &end
+                ));
+            }
+            let size = end.checked_sub(&start)?;
+            Range::new(start, size)
+        } else if let Some((start_str, size_str)) = split_top_level(value, "+") {
+            let start = ByteOffset::from_str(start_str)
+                .map_err(|e| err!(Parse, "Failed to parse start {}", e))?;
+            let size = ByteOffset::from_str(size_str)
+                .map_err(|e| err!(Parse, "Failed to parse size {}", e))?;
+            Range::new(start, size)
+        } else {
+            return Err(err!(Parse, "Failed to parse {} to Range", value));
+        };
+        range.end()?;
+        Ok(range)
+    }
 }
 
 impl<'de> de::Deserialize<'de> for Range {
@@ -36,68 +82,7 @@ impl<'de> de::Deserialize<'de> for Range {
             where
                 E: de::Error,
             {
-                lazy_static! {
-                    static ref REGEX: Regex = Regex::new(
-                        r"^((?:(0[xX]){1}([A-Fa-f0-9]+))|(?:[0-9]+([KMGTE]i?)?))(\.\.|\+)((?:(0[xX]){1}([A-Fa-f0-9]+))|(?:[0-9]+([KMGTE]i?)?))$"
-                    )
-                    .unwrap();
-                }
-
-                let range = REGEX
-                    .captures(value)
-                    .ok_or_else(|| {
-                        Err::<Captures, Error>(format_err!("Failed to parse {} to Range", value))
-                    })
-                    .and_then(|captures| {
-                        if captures.len() == 10 {
-                            let start_str = &captures[1];
-                            let size_or_end_str = &captures[6];
-                            let start: ByteOffset =
-                                start_str.parse::<ByteOffset>().map_err(|e| {
-                                    Err::<Captures, Error>(format_err!(
-                                        "Failed to parse start {}",
-                                        e
-                                    ))
-                                })?;
-                            let size: ByteOffset = match &captures[5] {
-                                ".." => {
-                                    let end =
-                                        size_or_end_str.parse::<ByteOffset>().map_err(|e| {
-                                            Err::<Captures, Error>(format_err!(
-                                                "Failed to parse end {}",
-                                                e
-                                            ))
-                                        })?;
-                                    if start > end {
-                                        return Err(Err(format_err!(
-                                            "Start {} must be greater than end {}",
-                                            &start,
-                                            &end
-                                        )));
-                                    } else {
-                                        end - start.clone()
-                                    }
-                                }
-                                "+" => size_or_end_str.parse::<ByteOffset>().map_err(|e| {
-                                    Err::<Captures, Error>(format_err!(
-                                        "Failed to parse size {}",
-                                        e
-                                    ))
-                                })?,
-                                _ => {
-                                    return Err(Err(format_err!(
-                                        "Failed to parse {}",
-                                        &captures[5]
-                                    )));
-                                }
-                            };
-                            Ok(Range::new(start, size))
-                        } else {
-                            Ok(Default::default())
-                        }
-                    })
-                    .map_err(|e| E::custom(format!("{:?}", e)))?;
-                Ok(range)
+                Range::from_str(value).map_err(|e| E::custom(e.to_string()))
             }
         }
         deserializer.deserialize_str(RangeVisitor)
@@ -0,0 +1,228 @@
+use regex::bytes::{Regex, RegexBuilder};
+use serde::de;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+
+/// A byte pattern to search for, parsed from `<type>:<pattern>`.
+///
+/// * `hex:DEADBEEF` raw bytes, `??` matches any byte, whitespace is ignored
+/// * `str:v1.2.3` UTF-8 string
+/// * `regex:v[0-9]+\.[0-9]+` regular expression on bytes, `\xFF` matches
+///   the raw byte
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `None` is a wildcard.
+    Bytes(Vec<Option<u8>>),
+    Regex(Regex),
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Pattern::Bytes(a), Pattern::Bytes(b)) => a == b,
+            (Pattern::Regex(a), Pattern::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Pattern {
+    fn hex(s: &str) -> Result<Vec<Option<u8>>> {
+        let digits: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.len() % 2 != 0 {
+            return Err(err!(Parse, "Odd number of hex digits in {}", s));
+        }
+        digits
+            .chunks(2)
+            .map(|pair| match pair {
+                ['?', '?'] => Ok(None),
+                [high, low] => {
+                    let byte: String = [*high, *low].iter().collect();
+                    u8::from_str_radix(&byte, 16)
+                        .map(Some)
+                        .map_err(|e| err!(Parse, "Failed to parse {} as hex: {}", byte, e))
+                }
+                _ => unreachable!("Chunks of two. qed"),
+            })
+            .collect()
+    }
+
+    fn matches_at(pattern: &[Option<u8>], bytes: &[u8]) -> bool {
+        pattern.len() <= bytes.len()
+            && pattern
+                .iter()
+                .zip(bytes.iter())
+                .all(|(expected, byte)| expected.map_or(true, |expected| expected == *byte))
+    }
+
+    /// All matches in `bytes` starting at a multiple of `alignment`, in
+    /// ascending order, matches may overlap.
+    pub fn find_all(&self, bytes: &[u8], alignment: usize) -> Vec<Range<usize>> {
+        self.find(bytes, alignment, true)
+    }
+
+    /// Non-overlapping matches in `bytes` starting at a multiple of
+    /// `alignment`, in ascending order, the search resumes at the end of
+    /// each match.
+    pub fn find_non_overlapping(&self, bytes: &[u8], alignment: usize) -> Vec<Range<usize>> {
+        self.find(bytes, alignment, false)
+    }
+
+    fn find(&self, bytes: &[u8], alignment: usize, overlapping: bool) -> Vec<Range<usize>> {
+        let alignment = alignment.max(1);
+        let mut matches = Vec::new();
+        let mut pos = 0;
+        while pos <= bytes.len() {
+            let found = match self {
+                Pattern::Bytes(pattern) => (pos..bytes.len())
+                    .step_by(alignment)
+                    .find(|start| Self::matches_at(pattern, &bytes[*start..]))
+                    .map(|start| start..start + pattern.len()),
+                Pattern::Regex(regex) => regex.find_at(bytes, pos).map(|m| m.start()..m.end()),
+            };
+            let found = match found {
+                Some(found) => found,
+                None => break,
+            };
+            if found.start % alignment == 0 {
+                pos = if overlapping {
+                    found.start + 1
+                } else {
+                    std::cmp::max(found.end, found.start + 1)
+                };
+                matches.push(found);
+            } else {
+                pos = found.start + 1;
+            }
+            // continue at the next aligned offset
+            pos = (pos + alignment - 1) / alignment * alignment;
+        }
+        matches
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let idx = s
+            .find(':')
+            .ok_or_else(|| err!(Parse, "Missing type in {}, expected <type>:<pattern>", s))?;
+        let (kind, value) = (&s[..idx], &s[idx + 1..]);
+        let pattern = match kind {
+            "hex" => Pattern::Bytes(Self::hex(value)?),
+            "str" => Pattern::Bytes(value.bytes().map(Some).collect()),
+            "regex" => Pattern::Regex(
+                RegexBuilder::new(value)
+                    .unicode(false)
+                    .build()
+                    .map_err(|e| err!(Parse, "Failed to parse regex {}: {}", value, e))?,
+            ),
+            _ => return Err(err!(Parse, "Unknown pattern type {}", kind)),
+        };
+        match &pattern {
+            Pattern::Bytes(bytes) if bytes.is_empty() => Err(err!(Parse, "Pattern {} is empty", s)),
+            _ => Ok(pattern),
+        }
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct PatternVisitor;
+
+        impl<'de> de::Visitor<'de> for PatternVisitor {
+            type Value = Pattern;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expected a Pattern")
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::std::result::Result<Pattern, E>
+            where
+                E: de::Error,
+            {
+                Pattern::from_str(value).map_err(|e| E::custom(e.to_string()))
+            }
+        }
+        deserializer.deserialize_str(PatternVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find(pattern: &str, bytes: &[u8], alignment: usize) -> Vec<Range<usize>> {
+        Pattern::from_str(pattern)
+            .expect("Failed to parse")
+            .find_all(bytes, alignment)
+    }
+
+    fn find_non_overlapping(pattern: &str, bytes: &[u8], alignment: usize) -> Vec<Range<usize>> {
+        Pattern::from_str(pattern)
+            .expect("Failed to parse")
+            .find_non_overlapping(bytes, alignment)
+    }
+
+    #[test]
+    fn literal_and_wildcards() {
+        let bytes = b"\xDE\xAD\xBE\xEF..\xDE\xAD\x00\xEFv1.2";
+        assert_eq!(find("hex:DEADBEEF", bytes, 1), vec![0..4]);
+        assert_eq!(find("hex:DE AD ?? EF", bytes, 1), vec![0..4, 6..10]);
+        assert_eq!(find("hex:DE AD ?? EF", bytes, 4), vec![0..4]);
+        assert_eq!(find("str:v1.2", bytes, 1), vec![10..14]);
+        assert!(find("str:v1.3", bytes, 1).is_empty());
+    }
+
+    #[test]
+    fn overlapping() {
+        assert_eq!(
+            find("hex:AAAA", &[0xAA; 5], 1),
+            vec![0..2, 1..3, 2..4, 3..5]
+        );
+        assert_eq!(find("hex:AAAA", &[0xAA; 5], 2), vec![0..2, 2..4]);
+        let bytes = [0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA];
+        assert_eq!(find("hex:55AA55AA", &bytes, 1), vec![0..4, 2..6]);
+        assert_eq!(find("regex:\\xAA\\xAA", &[0xAA; 3], 1), vec![0..2, 1..3]);
+    }
+
+    #[test]
+    fn non_overlapping() {
+        assert_eq!(
+            find_non_overlapping("hex:AAAA", &[0xAA; 5], 1),
+            vec![0..2, 2..4]
+        );
+        let bytes = [0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA];
+        assert_eq!(find_non_overlapping("hex:55AA55AA", &bytes, 1), vec![0..4]);
+    }
+
+    #[test]
+    fn regex() {
+        let bytes = b"\xFFv1.22\xFFxv3.4\xFF";
+        assert_eq!(find(r"regex:v[0-9]+\.[0-9]+", bytes, 1), vec![1..6, 8..12]);
+        assert_eq!(find(r"regex:v[0-9]+\.[0-9]+", bytes, 2), vec![8..12]);
+        assert_eq!(find(r"regex:\xFF", bytes, 1), vec![0..1, 6..7, 12..13]);
+    }
+
+    #[test]
+    fn bad_patterns() {
+        [
+            "DEADBEEF", "hex:", "hex:ABC", "hex:?A", "str:", "regex:(", "re:x",
+        ]
+        .iter()
+        .for_each(|pattern| {
+            assert!(
+                Pattern::from_str(pattern).is_err(),
+                "{} should not parse",
+                pattern
+            )
+        });
+    }
+}
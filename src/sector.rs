@@ -0,0 +1,266 @@
+use serde::de;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::byte_offset::ByteOffset;
+use crate::error::{Error, Result};
+use crate::expression::split_top_level;
+use crate::range::Range;
+
+/// A run of `count` equally sized sectors.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SectorRegion {
+    pub sector_size: u64,
+    pub count: u64,
+}
+
+/// Sector layout of a flash device, starting at offset 0.
+///
+/// The regions are consecutive, the sector size of the last region is
+/// repeated for the remainder of the address space.
+/// This covers uniform devices as well as those with non-uniform sectors,
+/// i.e. the STM32F4 with `16Ki:4,64Ki,128Ki:7`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SectorMap {
+    regions: Vec<SectorRegion>,
+}
+
+impl SectorMap {
+    pub fn new(regions: Vec<SectorRegion>) -> Result<Self> {
+        if regions.is_empty() {
+            return Err(err!(Parse, "Sector map requires at least one region"));
+        }
+        if let Some(region) = regions.iter().find(|region| region.sector_size == 0) {
+            return Err(err!(Parse, "Sector size must not be zero {:?}", region));
+        }
+        Ok(Self { regions })
+    }
+
+    pub fn uniform(sector_size: u64) -> Result<Self> {
+        Self::new(vec![SectorRegion {
+            sector_size,
+            count: 1,
+        }])
+    }
+
+    /// Regions of the map, the last one repeats.
+    pub fn regions(&self) -> &[SectorRegion] {
+        &self.regions
+    }
+
+    /// The sector `offset` lies in, as `(start, size)`.
+    pub fn sector_containing(&self, offset: u64) -> (u64, u64) {
+        let mut start = 0u64;
+        let (last, explicit) = self.regions.split_last().expect("Never empty. qed");
+        for region in explicit {
+            let region_size = region.sector_size.saturating_mul(region.count);
+            if offset - start < region_size {
+                let idx = (offset - start) / region.sector_size;
+                return (start + idx * region.sector_size, region.sector_size);
+            }
+            start = start.saturating_add(region_size);
+        }
+        let idx = (offset - start) / last.sector_size;
+        (start + idx * last.sector_size, last.sector_size)
+    }
+
+    pub fn is_boundary(&self, offset: u64) -> bool {
+        self.sector_containing(offset).0 == offset
+    }
+
+    /// The first sector boundary at or after `offset`.
+    pub fn boundary_at_or_after(&self, offset: u64) -> Result<u64> {
+        let (start, size) = self.sector_containing(offset);
+        if start == offset {
+            Ok(offset)
+        } else {
+            start
+                .checked_add(size)
+                .ok_or_else(|| err!(OutOfBounds, "No sector boundary after {:#x}", offset))
+        }
+    }
+
+    /// All sectors as `(start, size)` which overlap with `[start, end)`.
+    pub fn sectors_between(&self, start: u64, end: u64) -> Vec<(u64, u64)> {
+        let mut sectors = Vec::new();
+        let mut offset = start;
+        while offset < end {
+            let sector = self.sector_containing(offset);
+            sectors.push(sector);
+            match sector.0.checked_add(sector.1) {
+                Some(next) => offset = next,
+                None => break,
+            }
+        }
+        sectors
+    }
+
+    pub fn check_offset(&self, what: &str, offset: &ByteOffset) -> Result<()> {
+        let offset = offset.as_u64()?;
+        if self.is_boundary(offset) {
+            Ok(())
+        } else {
+            let (start, size) = self.sector_containing(offset);
+            Err(err!(
+                Alignment,
+                "{} {:#x} is not aligned to a sector boundary, closest are {:#x} and {:#x}",
+                what,
+                offset,
+                start,
+                start.saturating_add(size)
+            ))
+        }
+    }
+
+    pub fn check_range(&self, what: &str, range: &Range) -> Result<()> {
+        self.check_offset(&format!("{} start", what), &range.start)?;
+        self.check_offset(&format!("{} end", what), &range.end()?)
+    }
+}
+
+impl FromStr for SectorMap {
+    type Err = Error;
+
+    /// Parses a comma separated list of `<sector size>[:<count>]`,
+    /// where the count defaults to one.
+    fn from_str(value: &str) -> ::std::result::Result<Self, Self::Err> {
+        let mut regions = Vec::new();
+        let mut remainder = value;
+        loop {
+            let (entry, rest) = match split_top_level(remainder, ",") {
+                Some((entry, rest)) => (entry, Some(rest)),
+                None => (remainder, None),
+            };
+            let (size_str, count) = match split_top_level(entry, ":") {
+                Some((size_str, count_str)) => (
+                    size_str,
+                    count_str
+                        .trim()
+                        .parse::<u64>()
+                        .map_err(|e| err!(Parse, "Failed to parse sector count {}", e))?,
+                ),
+                None => (entry, 1),
+            };
+            let sector_size = ByteOffset::from_str(size_str)?.as_u64()?;
+            if count == 0 {
+                return Err(err!(Parse, "Sector count must not be zero in {}", entry));
+            }
+            regions.push(SectorRegion { sector_size, count });
+            match rest {
+                Some(rest) => remainder = rest,
+                None => break,
+            }
+        }
+        SectorMap::new(regions)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for SectorMap {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct SectorMapVisitor;
+
+        impl<'de> de::Visitor<'de> for SectorMapVisitor {
+            type Value = SectorMap;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expected a sector size or sector map")
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::std::result::Result<SectorMap, E>
+            where
+                E: de::Error,
+            {
+                SectorMap::from_str(value).map_err(|e| E::custom(e.to_string()))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> ::std::result::Result<SectorMap, E>
+            where
+                E: de::Error,
+            {
+                SectorMap::uniform(value).map_err(|e| E::custom(e.to_string()))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> ::std::result::Result<SectorMap, E>
+            where
+                E: de::Error,
+            {
+                let value = u64::try_from(value).map_err(|e| E::custom(format!("{:?}", e)))?;
+                self.visit_u64(value)
+            }
+        }
+        deserializer.deserialize_str(SectorMapVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uniform() {
+        let map = SectorMap::from_str("4Ki").expect("Failed to parse");
+        assert_eq!(map, SectorMap::uniform(4096).unwrap());
+        assert!(map.is_boundary(0));
+        assert!(map.is_boundary(0x10_0000));
+        assert!(!map.is_boundary(0x10_0001));
+        assert_eq!(map.boundary_at_or_after(1).unwrap(), 4096);
+        assert_eq!(map.boundary_at_or_after(8192).unwrap(), 8192);
+        assert_eq!(map.sector_containing(5000), (4096, 4096));
+    }
+
+    #[test]
+    fn non_uniform() {
+        // STM32F407VG
+        let map = SectorMap::from_str("16Ki:4, 64Ki, 128Ki:7").expect("Failed to parse");
+        assert_eq!(map.regions().len(), 3);
+        assert!(map.is_boundary(0x4000));
+        assert!(map.is_boundary(0x1_0000));
+        assert!(!map.is_boundary(0x1_4000));
+        assert!(map.is_boundary(0x2_0000));
+        assert!(!map.is_boundary(0x3_0000));
+        assert!(map.is_boundary(0x4_0000));
+        assert!(map.is_boundary(0x10_0000));
+        assert_eq!(map.sector_containing(0x1_4000), (0x1_0000, 0x1_0000));
+        assert_eq!(map.boundary_at_or_after(0x2_0001).unwrap(), 0x4_0000);
+        assert_eq!(
+            map.sectors_between(0x8000, 0x2_0001),
+            vec![
+                (0x8000, 0x4000),
+                (0xC000, 0x4000),
+                (0x1_0000, 0x1_0000),
+                (0x2_0000, 0x2_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn check() {
+        let map = SectorMap::from_str("16Ki:4,64Ki,128Ki").unwrap();
+        let range = Range::from_str("16Ki+48Ki").unwrap();
+        assert!(map.check_range("range", &range).is_ok());
+        let range = Range::from_str("16Ki+64Ki").unwrap();
+        assert!(map.check_range("range", &range).is_err());
+        assert!(map
+            .check_offset("offset", &ByteOffset::from_u64(0x2_0000))
+            .is_ok());
+        assert!(map
+            .check_offset("offset", &ByteOffset::from_u64(0x3_0000))
+            .is_err());
+    }
+
+    #[test]
+    fn bad_maps() {
+        let bad = ["", "0", "4Ki:0", "4Ki:x", "4Ki,,8Ki"];
+        bad.iter().for_each(|map| {
+            assert!(
+                SectorMap::from_str(map).is_err(),
+                "{} should not parse",
+                map
+            );
+        });
+    }
+}
@@ -0,0 +1,202 @@
+use serde_derive::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::info::sha256_hex;
+use crate::ops::{write_output, AnnotatedBytes, FillPattern};
+
+const INDEX_PLACEHOLDER: &str = "{index}";
+
+/// A chunk written by split.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Chunk {
+    pub index: usize,
+    pub file: PathBuf,
+    /// Offset of the first byte within the image.
+    pub offset: u64,
+    /// Size of the chunk file including the padding.
+    pub size: u64,
+    pub crc32: u32,
+    pub sha256: String,
+}
+
+/// Lists all chunks of an image, saved as TOML:
+///
+/// ```toml
+/// chunk_size = 4096
+///
+/// [[chunk]]
+/// index = 0
+/// file = "part_0.bin"
+/// offset = 0
+/// size = 4096
+/// crc32 = 2351213232
+/// sha256 = "..."
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    pub chunk_size: u64,
+    #[serde(rename = "chunk")]
+    pub chunks: Vec<Chunk>,
+}
+
+impl Manifest {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)
+            .map_err(|e| err!(Format, "Failed to serialize the manifest: {}", e))?;
+        write_output(path, content.as_bytes())
+    }
+}
+
+/// Cut `image` into chunks of `chunk_size` bytes, the last one is padded
+/// to the full chunk size with `pad` if given.
+///
+/// The manifest lists the chunks with their files named after `template`,
+/// see `indexed_path`.
+pub fn split(
+    image: &AnnotatedBytes,
+    chunk_size: usize,
+    pad: Option<&FillPattern>,
+    template: &Path,
+) -> Result<(Vec<AnnotatedBytes>, Manifest)> {
+    if chunk_size == 0 {
+        return Err(err!(Parse, "Chunk size must not be zero"));
+    }
+    let mut chunks = Vec::new();
+    let mut manifest = Manifest {
+        chunk_size: chunk_size as u64,
+        chunks: Vec::new(),
+    };
+    for (index, data) in image.bytes.chunks(chunk_size).enumerate() {
+        let offset = index * chunk_size;
+        let end = offset + data.len();
+        // same as stance of offset+chunk_size, without copying the whole image
+        let mut chunk = AnnotatedBytes::new();
+        chunk.bytes.extend_from_slice(data);
+        chunk.base_address = image
+            .base_address
+            .map(|base| {
+                base.checked_add(offset as u64).ok_or_else(|| {
+                    err!(
+                        OutOfBounds,
+                        "Address of chunk {} exceeds the 64 bit range",
+                        index
+                    )
+                })
+            })
+            .transpose()?;
+        chunk.holes = image
+            .holes
+            .iter()
+            .map(|hole| hole.start.max(offset) - offset..hole.end.min(end) - offset)
+            .filter(|hole| hole.start < hole.end)
+            .collect();
+        if let Some(fill_pattern) = pad {
+            chunk.resize(chunk_size, fill_pattern)?;
+        }
+
+        manifest.chunks.push(Chunk {
+            index,
+            file: indexed_path(template, index)?,
+            offset: offset as u64,
+            size: chunk.bytes.len() as u64,
+            crc32: crc32fast::hash(&chunk.bytes),
+            sha256: sha256_hex(&chunk.bytes),
+        });
+        chunks.push(chunk);
+    }
+    Ok((chunks, manifest))
+}
+
+/// Path of the output file `index` such as a chunk or a lane, the
+/// `{index}` placeholder of `template` is replaced by the index.
+pub fn indexed_path(template: &Path, index: usize) -> Result<PathBuf> {
+    let template = template
+        .to_str()
+        .ok_or_else(|| err!(Parse, "Output {:?} is not valid UTF-8", template))?;
+    if !template.contains(INDEX_PLACEHOLDER) {
+        return Err(err!(
+            Parse,
+            "Output {} lacks the placeholder {}, all parts would be written to the same file",
+            template,
+            INDEX_PLACEHOLDER
+        ));
+    }
+    Ok(PathBuf::from(
+        template.replace(INDEX_PLACEHOLDER, &index.to_string()),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn template() {
+        assert_eq!(
+            indexed_path(Path::new("out/part_{index}.bin"), 12).unwrap(),
+            PathBuf::from("out/part_12.bin")
+        );
+        assert!(indexed_path(Path::new("part.bin"), 0).is_err());
+    }
+
+    #[test]
+    fn chunks() {
+        let image = AnnotatedBytes::parse(&[1, 2, 3, 4, 5], crate::MetaInfo::Bin).unwrap();
+        let template = Path::new("part_{index}.bin");
+        let (chunks, manifest) = split(&image, 2, None, template).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(&chunks[2].bytes[..], &[5]);
+        assert_eq!(manifest.chunks[1].offset, 2);
+        assert_eq!(manifest.chunks[2].file, PathBuf::from("part_2.bin"));
+        assert_eq!(manifest.chunks[2].size, 1);
+
+        let (chunks, manifest) = split(&image, 2, Some(&FillPattern::Zero), template).unwrap();
+        assert_eq!(&chunks[2].bytes[..], &[5, 0]);
+        assert_eq!(manifest.chunks[2].size, 2);
+        assert!(split(&image, 0, None, template).is_err());
+    }
+
+    #[test]
+    fn hex_chunks() {
+        // 4 bytes at 0x0800_0000 and 0x0800_0010 each
+        let content = b":020000040800F2\n:0400000001020304F2\n:04001000AABBCCDDDE\n:00000001FF";
+        let image = AnnotatedBytes::parse(content, crate::MetaInfo::IntelHex).unwrap();
+        let template = Path::new("part_{index}.hex");
+        let (chunks, _manifest) = split(&image, 8, None, template).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].base_address, Some(0x0800_0000));
+        assert_eq!(chunks[0].holes, vec![4..8]);
+        assert_eq!(chunks[1].base_address, Some(0x0800_0008));
+        assert_eq!(chunks[1].holes, vec![0..8]);
+        assert_eq!(chunks[2].base_address, Some(0x0800_0010));
+        assert!(chunks[2].holes.is_empty());
+
+        // written at their addresses, without records for the holes
+        let hex = chunks[2].clone().encode(crate::MetaInfo::IntelHex).unwrap();
+        assert_eq!(
+            &hex[..],
+            &b":020000040800F2\n:04001000AABBCCDDDE\n:00000001FF"[..]
+        );
+        let hex = chunks[1].clone().encode(crate::MetaInfo::IntelHex).unwrap();
+        assert_eq!(&hex[..], &b":00000001FF"[..]);
+    }
+
+    #[test]
+    fn manifest() {
+        let manifest = Manifest {
+            chunk_size: 4,
+            chunks: vec![Chunk {
+                index: 0,
+                file: PathBuf::from("part_0.bin"),
+                offset: 0,
+                size: 4,
+                crc32: 1,
+                sha256: "00".to_owned(),
+            }],
+        };
+        let content = toml::to_string(&manifest).expect("Failed to serialize");
+        assert!(content.starts_with("chunk_size = 4\n"));
+        assert!(content.contains("[[chunk]]\nindex = 0\nfile = \"part_0.bin\"\n"));
+    }
+}
@@ -0,0 +1,628 @@
+use log::warn;
+use std::cmp::{max, min};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::byte_offset::ByteOffset;
+use crate::error::{Error, Result};
+use crate::ops::{ContentSeed, FillPattern, GraftMode};
+use crate::sector::SectorMap;
+
+/// Bytes read or generated at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Names a source or the output of a streamed image in errors.
+pub trait Named {
+    fn name(&self) -> String;
+}
+
+impl<T> Named for Cursor<T> {
+    fn name(&self) -> String {
+        "buffer".to_owned()
+    }
+}
+
+impl Named for Vec<u8> {
+    fn name(&self) -> String {
+        "buffer".to_owned()
+    }
+}
+
+/// A file streamed from or written in place, named by its path.
+#[derive(Debug)]
+pub struct StreamedFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl StreamedFile {
+    /// Open `path` for reading.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).map_err(|e| Error::Io(format!("Failed to read {:?}", path), e))?;
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+        })
+    }
+
+    /// Open the existing `path` for writing in place.
+    pub fn open_write(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| Error::Io(format!("Failed to write {:?}", path), e))?;
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+        })
+    }
+
+    /// Size of the file in bytes.
+    pub fn size(&self) -> Result<u64> {
+        self.file
+            .metadata()
+            .map(|metadata| metadata.len())
+            .map_err(|e| Error::Io(format!("Failed to read {:?}", self.path), e))
+    }
+}
+
+impl Named for StreamedFile {
+    fn name(&self) -> String {
+        format!("{:?}", self.path)
+    }
+}
+
+impl Read for StreamedFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for StreamedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for StreamedFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+fn read_error<R: Named>(source: &R) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |e| Error::Io(format!("Failed to read {}", source.name()), e)
+}
+
+fn write_error<W: Named>(out: &W) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |e| Error::Io(format!("Failed to write {}", out.name()), e)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    /// `len` bytes of the source with index `source`, starting at `start`.
+    Copy {
+        source: usize,
+        start: u64,
+        len: u64,
+    },
+    Bytes(Vec<u8>),
+    /// `len` bytes of the fill pattern at `offset`.
+    Fill {
+        offset: u64,
+        len: u64,
+    },
+}
+
+impl Piece {
+    fn len(&self) -> u64 {
+        match self {
+            Piece::Copy { len, .. } | Piece::Fill { len, .. } => *len,
+            Piece::Bytes(bytes) => bytes.len() as u64,
+        }
+    }
+
+    /// Bytes `start..end` relative to the first byte of the piece.
+    fn slice(&self, start: u64, end: u64) -> Piece {
+        match self {
+            Piece::Copy {
+                source, start: s, ..
+            } => Piece::Copy {
+                source: *source,
+                start: s + start,
+                len: end - start,
+            },
+            Piece::Bytes(bytes) => Piece::Bytes(bytes[start as usize..end as usize].to_vec()),
+            Piece::Fill { offset, .. } => Piece::Fill {
+                offset: offset + start,
+                len: end - start,
+            },
+        }
+    }
+}
+
+/// An image composed of pieces of sources, literal bytes and padding.
+///
+/// The sources are only read when the image is written, so editing large
+/// binary files takes memory independent of their size. `stance`, `patch`
+/// and `graft` yield the same bytes as those of `AnnotatedBytes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamedBytes {
+    pieces: Vec<Piece>,
+}
+
+impl StreamedBytes {
+    /// All `len` bytes of the source with index `source`.
+    pub fn source(source: usize, len: u64) -> Self {
+        StreamedBytes {
+            pieces: vec![Piece::Copy {
+                source,
+                start: 0,
+                len,
+            }],
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.pieces.iter().map(Piece::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes `start..end`, clamped to the image.
+    fn slice(&self, start: u64, end: u64) -> Self {
+        let mut pieces = Vec::new();
+        let mut pos = 0;
+        for piece in self.pieces.iter() {
+            let len = piece.len();
+            let (from, to) = (max(start, pos), min(end, pos + len));
+            if from < to {
+                pieces.push(piece.slice(from - pos, to - pos));
+            }
+            pos += len;
+        }
+        StreamedBytes { pieces }
+    }
+
+    fn append(&mut self, other: Self) {
+        self.pieces.extend(other.pieces);
+    }
+
+    /// Pad with `len` bytes of the fill pattern, anchored at the current end.
+    fn pad(&mut self, len: u64) {
+        if len > 0 {
+            let offset = self.len();
+            self.pieces.push(Piece::Fill { offset, len });
+        }
+    }
+
+    pub fn stance(&mut self, start: &ByteOffset, size: &ByteOffset) -> Result<()> {
+        let start_idx = start.as_u64()?;
+        let len = self.len();
+        if start_idx > len {
+            warn!("start {} is outside file size {}", start, len);
+        }
+        let end_idx = start_idx.saturating_add(size.as_u64()?);
+        *self = self.slice(start_idx, end_idx);
+        Ok(())
+    }
+
+    /// Overwrite the bytes at `at` with `value`, the image size is unchanged.
+    pub fn patch(&mut self, at: &ByteOffset, value: &[u8]) -> Result<()> {
+        let len = self.len();
+        let start = at.as_u64()?;
+        let end = start
+            .checked_add(value.len() as u64)
+            .filter(|end| *end <= len)
+            .ok_or_else(|| {
+                err!(
+                    OutOfBounds,
+                    "Patch of {} bytes at {} exceeds the image size {}",
+                    value.len(),
+                    at,
+                    len
+                )
+            })?;
+        let mut patched = self.slice(0, start);
+        patched.pieces.push(Piece::Bytes(value.to_vec()));
+        patched.append(self.slice(end, len));
+        *self = patched;
+        Ok(())
+    }
+
+    pub fn graft(
+        &mut self,
+        replace: StreamedBytes,
+        start: &ByteOffset,
+        size: &ByteOffset,
+        mode: GraftMode,
+    ) -> Result<()> {
+        // [prefix replacement padding postfix]
+        let len = self.len();
+        let start_idx = start.as_u64()?;
+        let size_idx = mode.range_size(len, start_idx, size.as_u64()?)?;
+        let end_idx = start_idx + size_idx;
+
+        if end_idx > len {
+            self.pad(end_idx - len);
+        }
+
+        let mut replacement = replace;
+        if replacement.len() > size_idx {
+            match mode {
+                GraftMode::Strict => {
+                    return Err(err!(
+                        OutOfBounds,
+                        "Failed to graft {} bytes, size {} is smaller than replacing bytes",
+                        replacement.len(),
+                        size
+                    ));
+                }
+                GraftMode::Truncate => replacement = replacement.slice(0, size_idx),
+                // the tail is shifted back by the excess
+                GraftMode::Grow => {}
+            }
+        }
+
+        let mut grafted = self.slice(0, start_idx);
+        grafted.append(replacement);
+
+        // fill missing bytes
+        let filled = grafted.len();
+        grafted.pad(end_idx.saturating_sub(filled));
+
+        grafted.append(self.slice(end_idx, self.len()));
+        *self = grafted;
+        Ok(())
+    }
+
+    /// Pad the end with the fill pattern up to the next sector boundary.
+    pub fn pad_to_sector(&mut self, sectors: &SectorMap) -> Result<()> {
+        let len = self.len();
+        let end = sectors.boundary_at_or_after(len)?;
+        self.pad(end - len);
+        Ok(())
+    }
+
+    /// Write the image to `out`, reading the pieces of `sources` in chunks.
+    pub fn write_to<R: Read + Seek + Named, W: Write + Named>(
+        &self,
+        sources: &mut [R],
+        out: &mut W,
+        fill_pattern: &FillPattern,
+    ) -> Result<()> {
+        for piece in self.pieces.iter() {
+            Self::write_piece(piece, sources, out, fill_pattern)?;
+        }
+        Ok(())
+    }
+
+    /// Write only the pieces which differ from the source `target` of
+    /// `target_len` bytes, `out` writes to the same file as `target`.
+    ///
+    /// Nothing is written and `false` returned if bytes of `target` move or
+    /// the image shrinks, then the whole image must be rewritten.
+    pub fn write_in_place<R: Read + Seek + Named, W: Write + Seek + Named>(
+        &self,
+        target: usize,
+        target_len: u64,
+        sources: &mut [R],
+        out: &mut W,
+        fill_pattern: &FillPattern,
+    ) -> Result<bool> {
+        let mut changes = Vec::new();
+        let mut pos = 0;
+        for piece in self.pieces.iter() {
+            match piece {
+                Piece::Copy { source, start, .. } if *source == target => {
+                    if *start != pos {
+                        return Ok(false);
+                    }
+                }
+                _ => changes.push((pos, piece)),
+            }
+            pos += piece.len();
+        }
+        if pos < target_len {
+            return Ok(false);
+        }
+
+        for (pos, piece) in changes {
+            out.seek(SeekFrom::Start(pos)).map_err(write_error(out))?;
+            Self::write_piece(piece, sources, out, fill_pattern)?;
+        }
+        out.flush().map_err(write_error(out))?;
+        Ok(true)
+    }
+
+    fn write_piece<R: Read + Seek + Named, W: Write + Named>(
+        piece: &Piece,
+        sources: &mut [R],
+        out: &mut W,
+        fill_pattern: &FillPattern,
+    ) -> Result<()> {
+        match piece {
+            Piece::Copy { source, start, len } => {
+                let source = sources
+                    .get_mut(*source)
+                    .ok_or_else(|| err!(OutOfBounds, "Missing source {}", source))?;
+                source
+                    .seek(SeekFrom::Start(*start))
+                    .map_err(read_error(source))?;
+                let mut chunk = vec![0u8; min(*len, CHUNK_SIZE as u64) as usize];
+                let mut copied = 0u64;
+                while copied < *len {
+                    let want = min(*len - copied, chunk.len() as u64) as usize;
+                    let read = source
+                        .read(&mut chunk[..want])
+                        .map_err(read_error(source))?;
+                    if read == 0 {
+                        break;
+                    }
+                    out.write_all(&chunk[..read]).map_err(write_error(out))?;
+                    copied += read as u64;
+                }
+                if copied != *len {
+                    return Err(err!(
+                        Format,
+                        "Source ended after {} of {} bytes at {:#x}",
+                        copied,
+                        len,
+                        start
+                    ));
+                }
+            }
+            Piece::Bytes(bytes) => out.write_all(bytes).map_err(write_error(out))?,
+            Piece::Fill { offset, len } => {
+                let end = offset + len;
+                let mut pos = *offset;
+                while pos < end {
+                    let chunk = min(end - pos, CHUNK_SIZE as u64);
+                    let padding = fill_pattern.generate(usize::try_from(pos)?, chunk as usize)?;
+                    out.write_all(&padding).map_err(write_error(out))?;
+                    pos += chunk;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `content_seed` of all `sources`, read in chunks.
+pub fn content_seed<R: Read + Seek + Named>(sources: &mut [R]) -> Result<u64> {
+    let mut seed = ContentSeed::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    for source in sources.iter_mut() {
+        source
+            .seek(SeekFrom::Start(0))
+            .map_err(read_error(source))?;
+        let mut len = 0u64;
+        loop {
+            let read = source.read(&mut buffer).map_err(read_error(source))?;
+            if read == 0 {
+                break;
+            }
+            seed.update(&buffer[..read]);
+            len += read as u64;
+        }
+        seed.end_input(len);
+    }
+    Ok(seed.seed())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ops::{content_seed as content_seed_of, AnnotatedBytes};
+    use std::io::Cursor;
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|idx| (idx * 7 % 251) as u8).collect()
+    }
+
+    fn write(streamed: &StreamedBytes, sources: &[&[u8]], fill_pattern: &FillPattern) -> Vec<u8> {
+        let mut sources: Vec<Cursor<&[u8]>> = sources.iter().map(|s| Cursor::new(*s)).collect();
+        let mut out = Vec::new();
+        streamed
+            .write_to(&mut sources, &mut out, fill_pattern)
+            .expect("Failed to write");
+        out
+    }
+
+    fn annotated(bytes: &[u8]) -> AnnotatedBytes {
+        let mut annotated = AnnotatedBytes::new();
+        annotated.bytes.extend_from_slice(bytes);
+        annotated
+    }
+
+    #[test]
+    fn stance_and_patch() {
+        let input = image(300);
+        let fill_pattern = FillPattern::Zero;
+
+        for (start, size) in [(0, 300), (10, 100), (250, 100), (400, 10)].iter() {
+            let mut streamed = StreamedBytes::source(0, 300);
+            streamed
+                .stance(&ByteOffset::from_u64(*start), &ByteOffset::from_u64(*size))
+                .unwrap();
+            let mut expected = annotated(&input);
+            expected
+                .stance(ByteOffset::from_u64(*start), ByteOffset::from_u64(*size))
+                .unwrap();
+            assert_eq!(streamed.len(), expected.bytes.len() as u64);
+            assert_eq!(
+                write(&streamed, &[&input], &fill_pattern),
+                &expected.bytes[..]
+            );
+        }
+
+        let mut streamed = StreamedBytes::source(0, 300);
+        let mut expected = annotated(&input);
+        for (at, value) in [
+            (0u64, &[1u8, 2][..]),
+            (100, &[3, 4, 5]),
+            (101, &[6]),
+            (298, &[7, 8]),
+        ]
+        .iter()
+        {
+            streamed.patch(&ByteOffset::from_u64(*at), value).unwrap();
+            expected.patch(&ByteOffset::from_u64(*at), value).unwrap();
+        }
+        assert_eq!(
+            write(&streamed, &[&input], &fill_pattern),
+            &expected.bytes[..]
+        );
+        assert!(streamed.patch(&ByteOffset::from_u64(299), &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn graft_modes() {
+        let input = image(200);
+        let replacement = [0xAAu8; 50];
+        let fill_pattern = FillPattern::SeededRandom(7);
+
+        let cases = [
+            (10, 80, GraftMode::Strict),
+            (150, 50, GraftMode::Strict),
+            (10, 20, GraftMode::Truncate),
+            (180, 60, GraftMode::Truncate),
+            (200, 60, GraftMode::Truncate),
+            (250, 60, GraftMode::Truncate),
+            (10, 20, GraftMode::Grow),
+            (180, 60, GraftMode::Grow),
+            (250, 60, GraftMode::Grow),
+            (180, 60, GraftMode::Strict),
+            (10, 20, GraftMode::Strict),
+        ];
+        for (start, size, mode) in cases.iter() {
+            let (start, size) = (ByteOffset::from_u64(*start), ByteOffset::from_u64(*size));
+            let mut streamed = StreamedBytes::source(0, 200);
+            let result = streamed.graft(StreamedBytes::source(1, 50), &start, &size, *mode);
+            let mut expected = annotated(&input);
+            let expected_result = expected.graft(
+                annotated(&replacement),
+                start.clone(),
+                size.clone(),
+                fill_pattern.clone(),
+                *mode,
+            );
+            assert_eq!(
+                result.is_ok(),
+                expected_result.is_ok(),
+                "{}+{} {:?}",
+                start,
+                size,
+                mode
+            );
+            if result.is_ok() {
+                assert_eq!(
+                    write(&streamed, &[&input, &replacement], &fill_pattern),
+                    &expected.bytes[..],
+                    "{}+{} {:?}",
+                    start,
+                    size,
+                    mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pad_and_seed() {
+        let input = image(100);
+        let fill_pattern = FillPattern::Address {
+            width: 4,
+            endianness: crate::ops::Endianness::Little,
+        };
+        let sectors = SectorMap::uniform(64).unwrap();
+
+        let mut streamed = StreamedBytes::source(0, 100);
+        streamed.pad_to_sector(&sectors).unwrap();
+        let mut expected = annotated(&input);
+        expected.pad_to_sector(&sectors, &fill_pattern).unwrap();
+        assert_eq!(streamed.len(), 128);
+        assert_eq!(
+            write(&streamed, &[&input], &fill_pattern),
+            &expected.bytes[..]
+        );
+
+        let other = image(3);
+        let mut sources = vec![Cursor::new(&input[..]), Cursor::new(&other[..])];
+        assert_eq!(
+            content_seed(&mut sources).unwrap(),
+            content_seed_of(vec![&input[..], &other[..]])
+        );
+    }
+
+    #[test]
+    fn in_place() {
+        let input = image(200);
+        let replacement = [0xAAu8; 50];
+        let fill_pattern = FillPattern::One;
+        let in_place = |streamed: &StreamedBytes| {
+            let mut sources = vec![Cursor::new(&input[..]), Cursor::new(&replacement[..])];
+            let mut out = Cursor::new(input.clone());
+            let written = streamed
+                .write_in_place(0, 200, &mut sources, &mut out, &fill_pattern)
+                .expect("Failed to write");
+            (written, out.into_inner())
+        };
+
+        // graft with padding and beyond the end of the image
+        for (start, size) in [(10u64, 80u64), (180, 60)].iter() {
+            let mut streamed = StreamedBytes::source(0, 200);
+            streamed
+                .graft(
+                    StreamedBytes::source(1, 50),
+                    &ByteOffset::from_u64(*start),
+                    &ByteOffset::from_u64(*size),
+                    GraftMode::Grow,
+                )
+                .unwrap();
+            let (written, out) = in_place(&streamed);
+            assert!(written);
+            assert_eq!(
+                out,
+                write(&streamed, &[&input, &replacement], &fill_pattern)
+            );
+        }
+
+        // a replacement larger than the range shifts the tail
+        let mut streamed = StreamedBytes::source(0, 200);
+        streamed
+            .graft(
+                StreamedBytes::source(1, 50),
+                &ByteOffset::from_u64(10),
+                &ByteOffset::from_u64(20),
+                GraftMode::Grow,
+            )
+            .unwrap();
+        assert_eq!(in_place(&streamed), (false, input.clone()));
+
+        let mut streamed = StreamedBytes::source(0, 200);
+        streamed
+            .stance(&ByteOffset::from_u64(0), &ByteOffset::from_u64(100))
+            .unwrap();
+        assert_eq!(in_place(&streamed), (false, input.clone()));
+    }
+
+    #[test]
+    fn short_source() {
+        let streamed = StreamedBytes::source(0, 10);
+        let mut sources = vec![Cursor::new(&[0u8; 5][..])];
+        let mut out = Vec::new();
+        assert!(streamed
+            .write_to(&mut sources, &mut out, &FillPattern::Zero)
+            .is_err());
+    }
+}
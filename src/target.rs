@@ -0,0 +1,241 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::ops::{is_stdio, write_output, AnnotatedBytes, AtomicWriter, FillPattern, MetaInfo};
+use crate::ops::{Output, SIGNATURE_LEN};
+use crate::stream::{StreamedBytes, StreamedFile};
+
+/// Where an edited image is saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A new file, `-` writes to stdout.
+    Output(PathBuf),
+    /// Replace the edited input, after copying it to its name with
+    /// `backup_suffix` appended.
+    InPlace {
+        input: PathBuf,
+        backup_suffix: Option<String>,
+    },
+}
+
+impl Target {
+    /// Path of the saved file.
+    pub fn path(&self) -> &Path {
+        match self {
+            Target::Output(path) => path,
+            Target::InPlace { input, .. } => input,
+        }
+    }
+
+    /// Whether `inputs` can be streamed to the target as `meta_out` instead
+    /// of being loaded into memory. `format` detects the format of the input
+    /// with the given index from its first `SIGNATURE_LEN` bytes.
+    ///
+    /// Streaming requires binary files, none of which is overwritten by the
+    /// output, besides the input edited in place, which is the first.
+    pub fn streamable<E, F>(
+        &self,
+        inputs: &[&Path],
+        meta_out: MetaInfo,
+        mut format: F,
+    ) -> std::result::Result<bool, E>
+    where
+        E: From<Error>,
+        F: FnMut(usize, &[u8]) -> std::result::Result<MetaInfo, E>,
+    {
+        if meta_out != MetaInfo::Bin {
+            return Ok(false);
+        }
+        let output = std::fs::canonicalize(self.path()).ok();
+        for (idx, path) in inputs.iter().enumerate() {
+            if is_stdio(path) {
+                return Ok(false);
+            }
+            let mut header = Vec::new();
+            File::open(path)
+                .and_then(|file| file.take(SIGNATURE_LEN as u64).read_to_end(&mut header))
+                .map_err(|e| Error::Io(format!("Failed to read {:?}", path), e))?;
+            if format(idx, &header)? != MetaInfo::Bin {
+                return Ok(false);
+            }
+            // the input edited in place is never read after it is written
+            let edited = matches!(self, Target::InPlace { .. }) && idx == 0;
+            if !edited && output.is_some() && output == std::fs::canonicalize(path).ok() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Save the edited input of format `meta_in` as `meta_out`. In place,
+    /// the format is kept, an IntelHex input is replaced atomically and
+    /// keeps the holes between its records.
+    pub fn save(&self, bytes: AnnotatedBytes, meta_in: MetaInfo, meta_out: MetaInfo) -> Result<()> {
+        let path = match self {
+            Target::Output(path) => return bytes.save(path, meta_out),
+            Target::InPlace { input, .. } => input,
+        };
+        if meta_out != meta_in {
+            return Err(err!(
+                Format,
+                "Editing in place keeps the format {:?} of the input",
+                meta_in
+            ));
+        }
+        let content = bytes.encode(meta_out)?;
+        self.backup()?;
+        match meta_out {
+            MetaInfo::Bin => write_output(path, &content),
+            MetaInfo::IntelHex => {
+                // records are rewritten as a whole
+                let mut out = AtomicWriter::create(path)?;
+                out.write_all(&content)
+                    .map_err(|e| Error::Io(format!("Failed to write {:?}", path), e))?;
+                out.commit()
+            }
+        }
+    }
+
+    /// Write a streamed image, in place only its changes to the input,
+    /// which is the first of `sources`.
+    pub fn save_streamed(
+        &self,
+        bytes: &StreamedBytes,
+        sources: &mut [StreamedFile],
+        fill_pattern: &FillPattern,
+    ) -> Result<()> {
+        let path = match self {
+            Target::Output(path) => {
+                let mut out = Output::create(path)?;
+                bytes.write_to(sources, &mut out, fill_pattern)?;
+                return out.commit();
+            }
+            Target::InPlace { input, .. } => input,
+        };
+        self.backup()?;
+        let len = sources
+            .first()
+            .ok_or_else(|| err!(OutOfBounds, "Missing the input {:?}", path))?
+            .size()?;
+        let mut out = StreamedFile::open_write(path)?;
+        if !bytes.write_in_place(0, len, sources, &mut out, fill_pattern)? {
+            let mut out = AtomicWriter::create(path)?;
+            bytes.write_to(sources, &mut out, fill_pattern)?;
+            out.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Copy the input edited in place to its backup, if any.
+    fn backup(&self) -> Result<()> {
+        if let Target::InPlace {
+            input,
+            backup_suffix: Some(suffix),
+        } = self
+        {
+            let mut backup = input.clone().into_os_string();
+            backup.push(suffix);
+            std::fs::copy(input, &backup).map_err(|e| {
+                Error::Io(format!("Failed to back up {:?} to {:?}", input, backup), e)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::byte_offset::ByteOffset;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("scalpel-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn in_place_hex() {
+        let dir = temp_dir("in-place-hex");
+        let path = dir.join("firmware.hex");
+        // 4 bytes at 0x0800_0000 and 0x0810_0000 each
+        let hex = ":020000040800F2\n:0400000001020304F2\n:020000040810E2\n:04000000AABBCCDDEE\n:00000001FF";
+        std::fs::write(&path, hex).unwrap();
+
+        let mut bytes = AnnotatedBytes::load(&path, MetaInfo::IntelHex).unwrap();
+        assert_eq!(bytes.base_address, Some(0x0800_0000));
+        bytes.patch(&ByteOffset::from_u64(0), &[0xAA]).unwrap();
+
+        let target = Target::InPlace {
+            input: path.clone(),
+            backup_suffix: Some(".orig".to_owned()),
+        };
+        assert!(target
+            .save(bytes.clone(), MetaInfo::IntelHex, MetaInfo::Bin)
+            .is_err());
+        target
+            .save(bytes, MetaInfo::IntelHex, MetaInfo::IntelHex)
+            .unwrap();
+
+        // only the patched record changes, no records fill the hole
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            hex.replace(":0400000001020304F2", ":04000000AA02030449")
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("firmware.hex.orig")).unwrap(),
+            hex
+        );
+        // no temporary file is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_place_streamed() {
+        let dir = temp_dir("in-place-streamed");
+        let path = dir.join("firmware.bin");
+        std::fs::write(&path, [0u8; 16]).unwrap();
+
+        let target = Target::InPlace {
+            input: path.clone(),
+            backup_suffix: Some(".orig".to_owned()),
+        };
+        let binary = |_: usize, _: &[u8]| Ok::<_, Error>(MetaInfo::Bin);
+        assert!(target.streamable(&[&path], MetaInfo::Bin, binary).unwrap());
+        assert!(!target
+            .streamable(&[&path], MetaInfo::IntelHex, binary)
+            .unwrap());
+        // the output must not overwrite another input
+        assert!(!target
+            .streamable(&[&path, &path], MetaInfo::Bin, binary)
+            .unwrap());
+
+        let mut bytes = StreamedBytes::source(0, 16);
+        bytes
+            .patch(&ByteOffset::from_u64(4), &[0xAA, 0xBB])
+            .unwrap();
+        let mut sources = [StreamedFile::open(&path).unwrap()];
+        target
+            .save_streamed(&bytes, &mut sources, &FillPattern::default())
+            .unwrap();
+
+        let mut expected = [0u8; 16];
+        expected[4..6].copy_from_slice(&[0xAA, 0xBB]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        assert_eq!(
+            std::fs::read(dir.join("firmware.bin.orig")).unwrap(),
+            [0u8; 16]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_input() {
+        let err = StreamedFile::open(Path::new("NonExisitingFileName")).unwrap_err();
+        assert!(err.to_string().contains("NonExisitingFileName"), "{}", err);
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
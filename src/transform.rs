@@ -0,0 +1,154 @@
+use serde::de;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::ops::swap_words;
+use crate::patch::PatchValue;
+
+/// A bitwise transformation of a byte slice.
+///
+/// * `xor:<value>` XOR with a repeated key stream, the key uses the syntax
+///   of a patch value, i.e. `xor:hex:A55A` or `xor:file:key.bin`
+/// * `invert` invert all bits
+/// * `bit-reverse` reverse the bit order within each byte
+/// * `swap:<width>` reverse the byte order within each word of `width` bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transform {
+    Xor(Vec<u8>),
+    Invert,
+    BitReverse,
+    Swap(usize),
+}
+
+impl Transform {
+    /// Transform `bytes` in place, the key stream of XOR starts with the
+    /// first byte.
+    pub fn apply(&self, bytes: &mut [u8]) -> Result<()> {
+        match self {
+            Transform::Xor(key) if key.is_empty() => return Err(err!(Parse, "XOR key is empty")),
+            Transform::Xor(key) => bytes
+                .iter_mut()
+                .zip(key.iter().cycle())
+                .for_each(|(byte, key)| *byte ^= key),
+            Transform::Invert => bytes.iter_mut().for_each(|byte| *byte = !*byte),
+            Transform::BitReverse => bytes
+                .iter_mut()
+                .for_each(|byte| *byte = byte.reverse_bits()),
+            Transform::Swap(width) => swap_words(bytes, *width)?,
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Transform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let (kind, value) = match s.find(':') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+        match (kind, value) {
+            ("xor", Some(key)) => match PatchValue::from_str(key)?.bytes {
+                key if key.is_empty() => Err(err!(Parse, "XOR key {} is empty", s)),
+                key => Ok(Transform::Xor(key)),
+            },
+            ("invert", None) => Ok(Transform::Invert),
+            ("bit-reverse", None) => Ok(Transform::BitReverse),
+            ("swap", Some(width)) => width
+                .parse::<usize>()
+                .map(Transform::Swap)
+                .map_err(|e| err!(Parse, "Failed to parse word width {}: {}", width, e)),
+            _ => Err(err!(Parse,
+                "Unknown transformation {}, expected xor:<value>, invert, bit-reverse or swap:<width>",
+                s
+            )),
+        }
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Transform {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct TransformVisitor;
+
+        impl<'de> de::Visitor<'de> for TransformVisitor {
+            type Value = Transform;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expected a Transform")
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::std::result::Result<Transform, E>
+            where
+                E: de::Error,
+            {
+                Transform::from_str(value).map_err(|e| E::custom(e.to_string()))
+            }
+        }
+        deserializer.deserialize_str(TransformVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(transform: &str, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut bytes = bytes.to_vec();
+        Transform::from_str(transform)?.apply(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn transforms() {
+        assert_eq!(
+            apply("xor:hex:FF00", &[0x12, 0x34, 0x56]).unwrap(),
+            vec![0xED, 0x34, 0xA9]
+        );
+        assert_eq!(apply("invert", &[0x00, 0xA5]).unwrap(), vec![0xFF, 0x5A]);
+        assert_eq!(
+            apply("bit-reverse", &[0x01, 0xA0]).unwrap(),
+            vec![0x80, 0x05]
+        );
+        assert_eq!(
+            apply("swap:2", &[0x01, 0x02, 0x03, 0x04]).unwrap(),
+            vec![0x02, 0x01, 0x04, 0x03]
+        );
+        assert!(apply("swap:4", &[0x01, 0x02]).is_err());
+        assert!(Transform::Xor(vec![]).apply(&mut [0x01]).is_err());
+    }
+
+    #[test]
+    fn composed() {
+        let transforms = ["xor:u16le:0x1234", "invert", "bit-reverse", "invert"];
+        let mut bytes = vec![0u8; 4];
+        transforms
+            .iter()
+            .map(|transform| Transform::from_str(transform).unwrap())
+            .try_for_each(|transform| transform.apply(&mut bytes))
+            .unwrap();
+        assert_eq!(bytes, vec![0x2C, 0x48, 0x2C, 0x48]);
+    }
+
+    #[test]
+    fn bad_transforms() {
+        ["xor", "xor:AB", "invert:1", "swap", "swap:x", "rot13"]
+            .iter()
+            .for_each(|transform| {
+                assert!(
+                    Transform::from_str(transform).is_err(),
+                    "{} should not parse",
+                    transform
+                )
+            });
+
+        let path = std::env::temp_dir().join(format!("scalpel-empty-{}.bin", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        assert!(Transform::from_str(&format!("xor:file:{}", path.display())).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}